@@ -0,0 +1,202 @@
+/* Automaton construction on generated grammars, against the old
+ * clone-per-edit construction, and the whole engine built from the
+ * grammar. Run with `cargo bench --bench construction`; with the in-place
+ * builder the per-combo cost stays flat as the grammar grows, while the
+ * old one's grows with it. */
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use ft_ality::automaton::Automaton;
+use ft_ality::engine::{engine_for_player, EngineOptions};
+use ft_ality::parse::{classify, parse_gmr, Rule};
+
+const BUTTONS: [&str; 12] = [
+    "[Up]", "[Down]", "[Left]", "[Right]", "[FP]", "[BP]", "[FK]", "[BK]", "[BL]", "[RN]", "[E]", "[P]",
+];
+
+/* One key per button, then `combos` random combos over them. */
+fn generated_gmr(combos: usize, seed: u64) -> String {
+    let mut x = seed;
+    let mut next = move || {
+        x = x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (x >> 33) as usize
+    };
+    let bindings = BUTTONS.iter().zip('a'..).map(|(b, key)| format!("{key} -> {b}\n"));
+    let combos = (0..combos).map(|i| {
+        let len = 4 + next() % 6;
+        let seq: Vec<&str> = (0..len).map(|_| BUTTONS[next() % BUTTONS.len()]).collect();
+        format!("{} -> Move {i}\n", seq.join(", "))
+    });
+    bindings.chain(combos).collect()
+}
+
+/* The construction `AutomatonBuilder` replaced, kept here only to be timed
+ * against it: every edit clones the states it touches, the symbol maps and
+ * the state list itself. */
+mod old {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use ft_ality::parse::Rule;
+
+    #[derive(Debug, Clone)]
+    pub struct State {
+        pub goto_: BTreeMap<usize, usize>,
+        pub fail: usize,
+        pub outputs: BTreeSet<String>,
+    }
+
+    fn empty_state() -> State {
+        State { goto_: BTreeMap::new(), fail: 0, outputs: BTreeSet::new() }
+    }
+
+    fn intern_symbol(sym_by_token: &BTreeMap<String, usize>, token_by_sym: &[String], tok: &str)
+        -> (BTreeMap<String, usize>, Vec<String>, usize)
+    {
+        match sym_by_token.get(tok) {
+            Some(&s) => (sym_by_token.clone(), token_by_sym.to_vec(), s),
+            None => {
+                let s = token_by_sym.len();
+                let mut new_sym = sym_by_token.clone();
+                let mut new_vec = token_by_sym.to_vec();
+                new_sym.insert(tok.to_string(), s);
+                new_vec.push(tok.to_string());
+                (new_sym, new_vec, s)
+            }
+        }
+    }
+
+    fn ensure_edge(states: &[State], from: usize, sym: usize) -> (Vec<State>, usize) {
+        if let Some(&nxt) = states[from].goto_.get(&sym) {
+            return (states.to_vec(), nxt);
+        }
+        let new_idx = states.len();
+        let mut new_states = states.to_vec();
+        let mut from_state = new_states[from].clone();
+        from_state.goto_.insert(sym, new_idx);
+        new_states[from] = from_state;
+        new_states.push(empty_state());
+        (new_states, new_idx)
+    }
+
+    fn add_output(states: &[State], at: usize, out: String) -> Vec<State> {
+        let mut new_states = states.to_vec();
+        let mut st = new_states[at].clone();
+        st.outputs.insert(out);
+        new_states[at] = st;
+        new_states
+    }
+
+    fn build_trie(combos: &[Rule]) -> Vec<State> {
+        let (states, _, _) = combos.iter().fold(
+            (vec![empty_state()], BTreeMap::new(), Vec::new()),
+            |(states, sym_by_tok, tok_by_sym), r| {
+                let (states, sym_by_tok, tok_by_sym, end) = r.sequence.iter().fold(
+                    (states.clone(), sym_by_tok.clone(), tok_by_sym.clone(), 0usize),
+                    |(st, smap, tmap, cur), t| {
+                        let (smap, tmap, s) = intern_symbol(&smap, &tmap, t.as_str());
+                        let (st, nxt) = ensure_edge(&st, cur, s);
+                        (st, smap, tmap, nxt)
+                    },
+                );
+                (add_output(&states, end, r.move_name.clone()), sym_by_tok, tok_by_sym)
+            },
+        );
+        states
+    }
+
+    fn failure_links(states: Vec<State>, start: usize) -> Vec<State> {
+        let root_children: Vec<usize> = states[start].goto_.values().copied().collect();
+        let states = root_children.iter().fold(states, |acc, &nxt| {
+            let mut s = acc.clone();
+            s[nxt].fail = start;
+            s
+        });
+
+        fn bfs(states: Vec<State>, front: Vec<usize>, back: Vec<usize>, start: usize) -> Vec<State> {
+            match (front.split_first(), back.is_empty()) {
+                (None, true) => states,
+                (None, false) => bfs(states, back.into_iter().rev().collect(), Vec::new(), start),
+                (Some((&r, rest)), _) => {
+                    let edges: Vec<(usize, usize)> = states[r].goto_.iter().map(|(&sym, &s)| (sym, s)).collect();
+                    let (states, back) = edges.into_iter().fold((states.clone(), back.clone()), |(st, bk), (sym, s)| {
+                        let mut bk = bk.clone();
+                        bk.push(s);
+                        let mut f = st[r].fail;
+                        let f_next = loop {
+                            if f == start || st[f].goto_.contains_key(&sym) {
+                                break st[f].goto_.get(&sym).copied().unwrap_or(start);
+                            }
+                            f = st[f].fail;
+                        };
+                        let mut st_new = st.clone();
+                        let mut node = st_new[s].clone();
+                        node.fail = f_next;
+                        node.outputs.extend(st_new[f_next].outputs.iter().cloned());
+                        st_new[s] = node;
+                        (st_new, bk)
+                    });
+                    bfs(states, rest.to_vec(), back, start)
+                }
+            }
+        }
+
+        bfs(states, root_children, Vec::new(), start)
+    }
+
+    pub fn from_combos(combos: &[Rule]) -> Vec<State> {
+        failure_links(build_trie(combos), 0)
+    }
+}
+
+/* The old construction is quadratic in time and memory; past this many
+ * combos it takes seconds and gigabytes. */
+const OLD_MAX_COMBOS: usize = 600;
+
+fn time_it<F: FnMut()>(mut f: F, runs: u32) -> Duration {
+    let start = Instant::now();
+    for _ in 0..runs {
+        f();
+    }
+    start.elapsed() / runs
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>8} {:>12} {:>12} {:>14} {:>12} {:>14} {:>12} {:>14}",
+        "combos", "states", "build", "compile", "build/combo", "engine", "engine/combo", "old build", "old/combo",
+    );
+    for &n in &[300usize, 600, 1_250, 2_500, 5_000, 10_000, 20_000, 40_000] {
+        let compiled = classify(&parse_gmr(&generated_gmr(n, 42)).unwrap());
+        let combos: Vec<Rule> = compiled.combos.clone();
+        let automaton = Automaton::from_combos(&combos);
+
+        let build = time_it(|| { black_box(Automaton::from_combos(black_box(&combos))); }, 5);
+        let compile = time_it(|| { black_box(automaton.compile()); }, 5);
+        let opts = EngineOptions::default();
+        let engine = time_it(|| { black_box(engine_for_player(black_box(&compiled), 1, &opts)); }, 3);
+        let old = (n <= OLD_MAX_COMBOS).then(|| {
+            let states = old::from_combos(&combos);
+            let fails: Vec<usize> = states.iter().map(|s| s.fail).collect();
+            let expected: Vec<usize> = (0..automaton.num_states()).map(|s| automaton.state_info(s).1).collect();
+            assert_eq!(fails, expected, "the builders disagree on {n} combos");
+            time_it(|| { black_box(old::from_combos(black_box(&combos))); }, 1)
+        });
+        let (old_build, old_per_combo) = match old {
+            Some(t) => (format!("{t:?}"), format!("{:?}", t / n as u32)),
+            None => ("-".to_string(), "-".to_string()),
+        };
+
+        println!(
+            "{:>8} {:>8} {:>12?} {:>12?} {:>14?} {:>12?} {:>14?} {:>12} {:>14}",
+            n,
+            automaton.num_states(),
+            build,
+            compile,
+            build / n as u32,
+            engine,
+            engine / n as u32,
+            old_build,
+            old_per_combo,
+        );
+    }
+}
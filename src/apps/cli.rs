@@ -1,25 +1,150 @@
-use std::time::Instant;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
 use std::time::Duration;
-use crate::engine::{step_keytok, engine_from_gmr_file, current_state_info, print_engine};
-use crate::input::io_shell::{enable_raw_mode, disable_raw_mode, read_key_token};
+use crate::attrs::MoveFilter;
+use crate::clock::{Clock, MonotonicClock};
+use crate::diag::{render_all, Diagnostic, Severity};
+use crate::lint::lint;
+use crate::parse::{classify, parse_gmr_file_all};
+use crate::dot::DotOptions;
+use crate::gmrtest::run_gmrtest_files;
+use crate::facing::{map_step, to_relative, Facing};
+use crate::engine::{
+    engine_from_gmr_file, current_state_info, move_label, moveset, select_character, EngineOptions, MatchSource, MoveEvent,
+    StepOutcome,
+};
+use crate::jsonl::{command_records, emit, key_records, timer_records};
+use crate::input::{parse_timed_token, InputEvent, KeyEventKind};
+use crate::input::io_shell::{read_key_token, RawMode};
+use crate::replay::{divergences, run_replay, Action, Replay};
+use crate::versus::{
+    flip_all, flush_versus, next_characters, print_versus, route, step_versus_outcome, tick_versus,
+    versus_from_gmr_file, VersusConfig, VersusState,
+};
 
-pub fn run_cli(path: &str, debug: bool, step_timeout_ms: u64) -> Result<(), String> {
-    let (cfg, mut st) = engine_from_gmr_file(path, Duration::from_millis(step_timeout_ms))?;
+/* Prefixed with "P1: " etc. when several players share the terminal;
+ * with `debug`, moves matched through a suffix say so. Moves the filter
+ * rejects are not shown. Returns how many moves were printed. */
+fn print_outcomes(versus: bool, debug: bool, filter: &MoveFilter, outcomes: Vec<(usize, StepOutcome)>) -> usize {
+    let mut printed = 0;
+    for (player, outcome) in outcomes {
+        let who = if versus { format!("P{}: ", player + 1) } else { String::new() };
+        for m in outcome.moves.iter().filter(|m| filter.matches(&m.info)) {
+            let via = if debug && m.source == MatchSource::Suffix { " (suffix)" } else { "" };
+            println!("{who}{} !!{via}", move_label(&m.name, &m.info, None));
+            printed += 1;
+        }
+        for v in outcome.violations {
+            println!("{who}✗ {}: {}", v.move_name, v.constraint);
+        }
+        for e in outcome.events {
+            if let MoveEvent::Cancelled(m) = e {
+                println!("{who}({m} cancelled)");
+            }
+        }
+    }
+    printed
+}
+
+/* Where moves go: `print_outcomes`, or JSON Lines (`crate::jsonl`) with
+ * nothing else on stdout. Each method returns how many moves it wrote. */
+struct Report<'a> {
+    debug: bool,
+    json: bool,
+    filter: &'a MoveFilter,
+}
+
+impl Report<'_> {
+    fn key(&self, cfg: &VersusConfig, before: &VersusState, after: &VersusState, ev: &InputEvent,
+           outcomes: Vec<(usize, StepOutcome)>) -> usize
+    {
+        if !self.json {
+            let written = print_outcomes(cfg.players.len() > 1, self.debug, self.filter, outcomes);
+            if self.debug {
+                print_debug(cfg, after, &ev.key);
+            }
+            return written;
+        }
+        emit(&key_records(cfg, before, after, ev, &outcomes, self.filter))
+    }
+
+    fn timer(&self, cfg: &VersusConfig, before: &VersusState, after: &VersusState, now_ms: u128,
+             outcomes: Vec<(usize, StepOutcome)>) -> usize
+    {
+        if !self.json {
+            return print_outcomes(cfg.players.len() > 1, self.debug, self.filter, outcomes);
+        }
+        emit(&timer_records(before, after, now_ms, &outcomes, self.filter))
+    }
+
+    /* `ctrl-f` / `ctrl-n`; only JSON Lines report them here. */
+    fn command(&self, before: &VersusState, after: &VersusState, key: &str, now_ms: u128) -> usize {
+        if !self.json {
+            return 0;
+        }
+        emit(&command_records(before, after, key, now_ms))
+    }
+}
+
+/* Where `keytok` left each player it was routed to. */
+fn print_debug(cfg: &VersusConfig, st: &VersusState, keytok: &str) {
+    let versus = cfg.players.len() > 1;
+    for player in route(cfg, keytok) {
+        let pst = &st.players[player];
+        let (outputs, fail) = current_state_info(&cfg.players[player], pst);
+        let who = if versus { format!("P{} ", player + 1) } else { String::new() };
+        if outputs.is_empty() {
+            println!("{who}{keytok}  ⇒  (no outputs)   [state={}, fail={}]", pst.cur_state, fail);
+        } else {
+            println!("{who}{keytok}  ⇒  {}   [state={}, fail={}]", outputs.join(", "), pst.cur_state, fail);
+        }
+    }
+}
 
-    print_engine(&cfg);
+/* With `record`, the session is saved there as a replay file on exit.
+ * With `json`, stdout carries JSON Lines only. */
+pub fn run_cli(
+    path: &str,
+    debug: bool,
+    opts: &EngineOptions,
+    filter: &MoveFilter,
+    record: Option<&str>,
+    json: bool,
+) -> Result<(), String> {
+    let (cfg, mut st) = versus_from_gmr_file(path, opts)?;
+    let report = Report { debug, json, filter };
+    let mut recording = record.map(|_| Replay::new(cfg.grammar_hash, opts));
 
-    if let Err(e) = enable_raw_mode() {
-        return Err(format!("Error enabling raw mode: {e}"));
+    eprint!("{}", render_all(&cfg.warnings));
+    if !json {
+        print_versus(&cfg, &st);
     }
 
-    let timeout = Duration::from_millis(500);
+    let raw = RawMode::enable().map_err(|e| format!("Error enabling raw mode: {e}"))?;
+
+    let clock = MonotonicClock::new();
+    /* Moves held back in deferred mode, or while a press may still become
+     * a longer chord, only fire from a tick, so poll often then; otherwise
+     * ticks just report timeouts, which can wait. */
+    let holds_back = opts.deferred || cfg.players.iter().flat_map(|p| &p.movesets).any(|m| !m.chords.is_empty());
+    let timeout = Duration::from_millis(if holds_back { 50 } else { 500 });
     let esc_tail_timeout = Duration::from_millis(120);
     loop {
         let keytok = match read_key_token(timeout, esc_tail_timeout) {
             Ok(Some(k)) => k,
-            Ok(None) => continue,
+            Ok(None) => {
+                let now = clock.now_ms();
+                let (st2, outcomes) = tick_versus(&cfg, st.clone(), now);
+                if let Some(r) = recording.as_mut() {
+                    r.push_moves(now, &outcomes);
+                }
+                report.timer(&cfg, &st, &st2, now, outcomes);
+                st = st2;
+                continue;
+            }
             Err(e) => {
-                disable_raw_mode();
+                drop(raw);
+                save_recording(recording, record, clock.now_ms())?;
                 return Err(format!("input error: {e}"));
             }
         };
@@ -27,26 +152,209 @@ pub fn run_cli(path: &str, debug: bool, step_timeout_ms: u64) -> Result<(), Stri
         if keytok == "ctrl-c" {
             break;
         }
+        /* as in batch mode and the SDL window, even with one character */
+        if keytok == "ctrl-n" {
+            let (before, now) = (st.clone(), clock.now_ms());
+            st = next_characters(&cfg, st);
+            if let Some(r) = recording.as_mut() {
+                r.push_action(now, Action::NextCharacter);
+            }
+            report.command(&before, &st, &keytok, now);
+            if !json {
+                println!();
+                print_versus(&cfg, &st);
+            }
+            continue;
+        }
+        if keytok == "ctrl-f" {
+            let (before, now) = (st.clone(), clock.now_ms());
+            st = flip_all(&cfg, st);
+            if let Some(r) = recording.as_mut() {
+                r.push_action(now, Action::Flip);
+            }
+            report.command(&before, &st, &keytok, now);
+            if !json {
+                let sides: Vec<String> = st.players.iter().map(|p| format!("{:?}", p.facing).to_lowercase()).collect();
+                println!("(sides swapped, now facing {})", sides.join(" / "));
+            }
+            continue;
+        }
 
-        let now_ms = Instant::now().elapsed().as_millis() as u128;
-        let (st2, outs) = step_keytok(&cfg, st, &keytok, now_ms);
+        let ev = InputEvent::press(&keytok, clock.now_ms());
+        let (st2, outcomes) = step_versus_outcome(&cfg, st.clone(), &ev);
+        if let Some(r) = recording.as_mut() {
+            r.push_action(ev.time, Action::Key(KeyEventKind::Press, keytok.clone()));
+            r.push_moves(ev.time, &outcomes);
+        }
+        report.key(&cfg, &st, &st2, &ev, outcomes);
         st = st2;
+    }
 
-        for m in outs {
-            println!("{m} !!");
-        }
+    drop(raw);
+    save_recording(recording, record, clock.now_ms())?;
+    if !json {
+        println!("Exiting...");
+    }
+    Ok(())
+}
+
+/* Non-interactive mode: key tokens from `source` (a file, or stdin for
+ * "-"), separated by whitespace or newlines, each optionally `tok@ms`.
+ * An untimed token comes just past the chord window after the previous
+ * one, so it is a step of its own; lines starting with '#' are skipped.
+ * Moves are written as in the terminal mode, as soon as each line is read.
+ * Returns how many moves were written. */
+pub fn run_batch(path: &str, debug: bool, opts: &EngineOptions, filter: &MoveFilter, source: &str, json: bool)
+    -> Result<usize, String>
+{
+    let (cfg, mut st) = versus_from_gmr_file(path, opts)?;
+    let report = Report { debug, json, filter };
+    eprint!("{}", render_all(&cfg.warnings));
 
-        if debug {
-            let (outputs, fail) = current_state_info(&cfg, st);
-            if outputs.is_empty() {
-                println!("{keytok}  ⇒  (no outputs)   [state={}, fail={}]", st.cur_state, fail);
-            } else {
-                println!("{keytok}  ⇒  {}   [state={}, fail={}]", outputs.join(", "), st.cur_state, fail);
+    let reader: Box<dyn BufRead> = if source == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(source).map_err(|e| format!("{source}: {e}"))?))
+    };
+    let name = if source == "-" { "stdin" } else { source };
+    let gap = opts.chord_window.as_millis() + 1;
+    let (mut last, mut printed) = (None, 0);
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| format!("{name}: {e}"))?;
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        for word in line.split_whitespace() {
+            let ev = parse_timed_token(word, last, gap).map_err(|e| format!("{name}:{}: {e}", idx + 1))?;
+            last = Some(ev.time);
+            match ev.key.as_str() {
+                "ctrl-n" => {
+                    let after = next_characters(&cfg, st.clone());
+                    printed += report.command(&st, &after, &ev.key, ev.time);
+                    st = after;
+                }
+                "ctrl-f" => {
+                    let after = flip_all(&cfg, st.clone());
+                    printed += report.command(&st, &after, &ev.key, ev.time);
+                    st = after;
+                }
+                _ => {
+                    /* players the key is not routed to still get their timers run */
+                    let (ticked_st, ticked) = tick_versus(&cfg, st.clone(), ev.time);
+                    let (st2, outcomes) = step_versus_outcome(&cfg, ticked_st.clone(), &ev);
+                    printed += report.timer(&cfg, &st, &ticked_st, ev.time, ticked);
+                    printed += report.key(&cfg, &ticked_st, &st2, &ev, outcomes);
+                    st = st2;
+                }
             }
         }
     }
 
-    disable_raw_mode();
-    println!("Exiting...");
-    Ok(())
+    let end = last.unwrap_or(0);
+    let (flushed, outcomes) = flush_versus(&cfg, st.clone());
+    printed += report.timer(&cfg, &st, &flushed, end, outcomes);
+    Ok(printed)
+}
+
+fn save_recording(recording: Option<Replay>, record: Option<&str>, now_ms: u128) -> Result<(), String> {
+    match (recording, record) {
+        (Some(mut r), Some(file)) => {
+            r.finish(now_ms);
+            r.save(file)?;
+            eprintln!("Session recorded to {file}");
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/* Plays `replay_path` back against the grammar at `path`, without a
+ * terminal, and lists every move that came out differently. Fails if the
+ * session diverged. */
+pub fn run_replay_file(path: &str, replay_path: &str) -> Result<(), String> {
+    let replay = Replay::load(replay_path)?;
+    let (cfg, st) = versus_from_gmr_file(path, &replay.options)?;
+    if cfg.grammar_hash != replay.grammar_hash {
+        eprintln!(
+            "warning: {replay_path} was recorded against another grammar (hash {:016x}, {path} is {:016x})",
+            replay.grammar_hash, cfg.grammar_hash,
+        );
+    }
+
+    let (_, replayed) = run_replay(&cfg, st, &replay);
+    let found = divergences(&replay.moves, &replayed);
+    for d in &found {
+        println!("{d}");
+    }
+    println!(
+        "{replay_path}: {} action(s), {} move(s) recorded, {} replayed, {} divergence(s)",
+        replay.actions.len(),
+        replay.moves.len(),
+        replayed.len(),
+        found.len(),
+    );
+    if found.is_empty() { Ok(()) } else { Err(format!("{replay_path}: replay diverged")) }
+}
+
+/* Writes the automaton for `path` as Graphviz DOT to `out` (stdout if None).
+ * Highlighted steps may be key tokens; bound keys are translated first.
+ * With character sections, `character` picks the moveset (default: the first). */
+pub fn run_dot(path: &str, opts: &DotOptions, character: Option<&str>, out: Option<&str>) -> Result<(), String> {
+    let (cfg, st) = engine_from_gmr_file(path, &EngineOptions::default())?;
+    let st = match character {
+        Some(name) => select_character(&cfg, st, name).ok_or_else(|| format!("{path}: no character named {name:?}"))?,
+        None => st,
+    };
+    let highlight: Vec<String> = opts
+        .highlight
+        .iter()
+        .map(|t| cfg.key_to_internal.get(t).unwrap_or(t))
+        .map(|t| map_step(t, &|m| to_relative(m, Facing::Right)))
+        .collect();
+    let dot = moveset(&cfg, &st).automaton.to_dot_with(&DotOptions { highlight, ..opts.clone() });
+    match out {
+        Some(file) => std::fs::write(file, dot).map_err(|e| format!("{file}: {e}")),
+        None => {
+            print!("{dot}");
+            Ok(())
+        }
+    }
+}
+
+/* Parse errors, discarded rules and lint findings for `path`, rustc-style.
+ * Fails (non-zero exit) only if there are errors. */
+pub fn run_check(path: &str) -> Result<(), String> {
+    let (grammar, parse_diags) = parse_gmr_file_all(path).map_err(|e| format!("{path}: {e}"))?;
+    let compiled = classify(&grammar);
+
+    let diags: Vec<Diagnostic> = parse_diags
+        .into_iter()
+        .chain(compiled.discarded.iter().map(|d| d.diagnostic()))
+        .chain(lint(&compiled))
+        .collect();
+
+    let count = |sev: Severity| diags.iter().filter(|d| d.severity == sev).count();
+    let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+
+    if !diags.is_empty() {
+        println!("{}", render_all(&diags));
+    }
+    println!("{path}: {errors} error(s), {warnings} warning(s)");
+
+    if errors > 0 { Err(format!("{path}: check failed")) } else { Ok(()) }
+}
+
+/* Runs each `.gmrtest` file against the grammar at `path`, printing a diff
+ * for every step that fired the wrong moves. Fails if any step did. */
+pub fn run_tests(path: &str, gmrtests: &[String]) -> Result<(), String> {
+    let failed = gmrtests.iter().try_fold(0, |failed, gmrtest| {
+        let (steps, mismatches) = run_gmrtest_files(path, gmrtest)?;
+        for m in &mismatches {
+            println!("{}", m.render());
+        }
+        println!("{gmrtest}: {steps} step(s), {} failure(s)", mismatches.len());
+        Ok::<_, String>(failed + mismatches.len())
+    })?;
+    if failed > 0 { Err(format!("{path}: {failed} step(s) failed")) } else { Ok(()) }
 }
@@ -1,20 +1,34 @@
 #![cfg(feature = "sdl")]
 
 use std::collections::VecDeque;
-use std::time::{Duration, Instant};
 
 use sdl2::event::Event;
 use sdl2::keyboard::{Keycode, Mod};
 use sdl2::pixels::Color;
 
+use crate::attrs::MoveFilter;
+use crate::clock::{Clock, MonotonicClock};
+use crate::diag::render_all;
 use crate::engine::{
-    bindings, combos_internal, current_state_info, display_for_internal, engine_from_gmr_file,
-    matched_prefix_len, step_keytok, EngineConfig, EngineState, print_engine
+    bindings, combo_views, current_state_info, display_for_combo_with, matched_prefix_len, move_label, moveset,
+    EngineConfig, EngineOptions, EngineState, MoveEvent, StepOutcome,
+};
+use crate::facing::Facing;
+use crate::input::{InputEvent, KeyEventKind};
+use crate::jsonl::{command_records, emit, key_records, timer_records};
+use crate::replay::{Action, Replay};
+use crate::versus::{
+    flip_all, next_characters, print_versus, step_versus_outcome, tick_versus, versus_from_gmr_file, VersusConfig,
+    VersusState,
 };
 
 #[derive(Debug, Clone)]
 enum AppEvent {
-    KeyTok(String),
+    Key(KeyEventKind, String),
+    NextCharacter,
+    Flip,
+    /* once per frame, for deferred moves */
+    Tick,
     Quit,
 }
 
@@ -22,10 +36,14 @@ type NowMs = u128;
 
 #[derive(Debug, Clone)]
 struct ViewState {
-    engine: EngineState,
-    recent_msgs: VecDeque<String>,
+    versus: VersusState,
+    /* one queue per player */
+    recent_msgs: Vec<VecDeque<String>>,
 }
 
+/* Width of one player's panel; the window holds one per player. */
+const PANEL_W: i32 = 900;
+
 fn keycode_letter(kc: Keycode) -> Option<char> {
     Some(match kc {
         Keycode::A=>'a', Keycode::B=>'b', Keycode::C=>'c', Keycode::D=>'d', Keycode::E=>'e',
@@ -70,29 +88,80 @@ fn map_sdl_event(ev: Event) -> Option<AppEvent> {
             }
             if let Some(tok) = keytok_from_sdl(kc, keymod) {
                 if tok == "ctrl-c" { return Some(AppEvent::Quit); }
-                Some(AppEvent::KeyTok(tok))
+                if tok == "ctrl-n" { return Some(AppEvent::NextCharacter); }
+                if tok == "ctrl-f" { return Some(AppEvent::Flip); }
+                Some(AppEvent::Key(KeyEventKind::Press, tok))
             } else {
                 None
             }
         }
+        Event::KeyUp { keycode: Some(kc), keymod, repeat, .. } if !repeat => {
+            keytok_from_sdl(kc, keymod).map(|tok| AppEvent::Key(KeyEventKind::Release, tok))
+        }
         _ => None,
     }
 }
 
-/* (cfg, state, event, now) -> new state */
-fn reduce(cfg: &EngineConfig, vs: &ViewState, ev: AppEvent, now_ms: NowMs) -> ViewState {
-    match ev {
-        AppEvent::Quit => vs.clone(),
-        AppEvent::KeyTok(tok) => {
-            let (engine2, outs) = step_keytok(cfg, vs.engine, &tok, now_ms);
-            let mut msgs = vs.recent_msgs.clone();
-            for m in outs {
-                if msgs.len() >= 8 { msgs.pop_front(); }
-                msgs.push_back(m);
-            }
-            ViewState { engine: engine2, recent_msgs: msgs }
+/* Recent messages with each player's outcome appended, 8 at most. Moves
+ * the filter rejects are left out. */
+fn push_outcomes(recent: &[VecDeque<String>], filter: &MoveFilter, outcomes: &[(usize, StepOutcome)])
+    -> Vec<VecDeque<String>>
+{
+    let mut msgs = recent.to_vec();
+    for (player, outcome) in outcomes {
+        let missed = outcome.violations.iter().map(|v| format!("✗ {}: {}", v.move_name, v.constraint));
+        let cancelled = outcome.events.iter().filter_map(|e| match e {
+            MoveEvent::Cancelled(m) => Some(format!("({m} cancelled)")),
+            MoveEvent::Confirmed(_) => None,
+        });
+        let moves = outcome
+            .moves
+            .iter()
+            .filter(|m| filter.matches(&m.info))
+            .map(|m| move_label(&m.name, &m.info, None));
+        for m in moves.chain(missed).chain(cancelled) {
+            let queue = &mut msgs[*player];
+            if queue.len() >= 8 { queue.pop_front(); }
+            queue.push_back(m);
         }
     }
+    msgs
+}
+
+/* (cfg, state, event, now) -> (new state, what the engines reported) */
+fn reduce(cfg: &VersusConfig, filter: &MoveFilter, vs: &ViewState, ev: AppEvent, now_ms: NowMs)
+    -> (ViewState, Vec<(usize, StepOutcome)>)
+{
+    let (versus, outcomes) = match ev {
+        AppEvent::Quit => (vs.versus.clone(), Vec::new()),
+        AppEvent::NextCharacter => (next_characters(cfg, vs.versus.clone()), Vec::new()),
+        AppEvent::Flip => (flip_all(cfg, vs.versus.clone()), Vec::new()),
+        AppEvent::Tick => tick_versus(cfg, vs.versus.clone(), now_ms),
+        AppEvent::Key(kind, key) => step_versus_outcome(cfg, vs.versus.clone(), &InputEvent { key, kind, time: now_ms }),
+    };
+    let recent_msgs = push_outcomes(&vs.recent_msgs, filter, &outcomes);
+    (ViewState { versus, recent_msgs }, outcomes)
+}
+
+/* What a replay file keeps of an event; ticks and quitting are not actions. */
+fn replay_action(ev: &AppEvent) -> Option<Action> {
+    match ev {
+        AppEvent::Key(kind, key) => Some(Action::Key(*kind, key.clone())),
+        AppEvent::NextCharacter => Some(Action::NextCharacter),
+        AppEvent::Flip => Some(Action::Flip),
+        AppEvent::Tick | AppEvent::Quit => None,
+    }
+}
+
+/* Arrow keys drawn as arrows, so the combo list shows the side in use. */
+fn arrow_label(key: &str) -> String {
+    match key {
+        "left" => "←".to_string(),
+        "right" => "→".to_string(),
+        "up" => "↑".to_string(),
+        "down" => "↓".to_string(),
+        k => k.to_string(),
+    }
 }
 
 #[derive(Clone)]
@@ -114,7 +183,16 @@ struct UiModel {
     footer: UiLine,
 }
 
-fn build_ui_model(cfg: &EngineConfig, st: &ViewState) -> UiModel {
+/* One player's panel; `player` is shown in the titles in versus mode. */
+fn build_ui_model(
+    cfg: &EngineConfig,
+    engine: &EngineState,
+    recent: &VecDeque<String>,
+    player: Option<usize>,
+    filter: &MoveFilter,
+)
+    -> UiModel
+{
     let col_norm  = (220, 220, 220);
     let col_hit   = (160, 240, 200);
     let col_bind  = (230, 230, 230);
@@ -123,6 +201,7 @@ fn build_ui_model(cfg: &EngineConfig, st: &ViewState) -> UiModel {
     let col_sub    = (200, 200, 200);
     let col_out    = (255, 215, 130);
     let col_recent = (255, 255, 160);
+    let col_missed = (255, 150, 150);
     let col_footer = (160, 160, 160);
 
     let left_bindings: Vec<UiLine> = bindings(cfg)
@@ -130,37 +209,50 @@ fn build_ui_model(cfg: &EngineConfig, st: &ViewState) -> UiModel {
         .map(|(key, internal)| UiLine { text: format!("{:>12}  →  {}", key, internal), rgb: col_bind })
         .collect();
 
-    let combos_lines: Vec<UiLine> = combos_internal(cfg)
+    let character = moveset(cfg, engine).character.as_deref();
+    let combos_lines: Vec<UiLine> = combo_views(cfg, engine)
         .iter()
-        .map(|(steps, mv)| {
-            let prefix_len = matched_prefix_len(cfg, st.engine.cur_state, steps);
-            let mut line = String::new();
-            for (i, internal) in steps.iter().enumerate() {
-                if i > 0 { line.push_str(" , "); }
-                let lbl = display_for_internal(cfg, internal);
-                line.push_str(&lbl);
-            }
-            line.push_str("  =>  ");
-            line.push_str(mv);
+        .filter(|view| filter.matches(&view.info))
+        .map(|view| {
+            let prefix_len = view
+                .variants
+                .iter()
+                .map(|steps| matched_prefix_len(cfg, engine, steps))
+                .max()
+                .unwrap_or(0);
+            let combo = display_for_combo_with(cfg, engine, view, &arrow_label);
+            let line = format!("{combo}  =>  {}", move_label(&view.move_name, &view.info, character));
             UiLine { text: line, rgb: if prefix_len > 0 { col_hit } else { col_norm } }
         })
         .collect();
 
-    let (outs_now, fail) = current_state_info(cfg, st.engine);
+    let (outs_now, fail) = current_state_info(cfg, engine);
+    let side = match engine.facing {
+        Facing::Right => "→",
+        Facing::Left => "←",
+    };
+    let who = player.map_or(String::new(), |p| format!("Player {p} — "));
+    let combos_title = match &moveset(cfg, engine).character {
+        Some(name) => format!("Available combos ({name}, ctrl-n for next):"),
+        None => "Available combos:".to_string(),
+    };
 
     UiModel {
-        left_title: UiLine { text: "Keyboard bindings:".to_string(), rgb: col_title_l },
+        left_title: UiLine { text: format!("{who}Keyboard bindings:"), rgb: col_title_l },
         left_bindings,
-        combos_title: UiLine { text: "Available combos:".to_string(), rgb: col_title_l },
+        combos_title: UiLine { text: combos_title, rgb: col_title_l },
         combos_lines,
         right_title: UiLine { text: "Automaton".to_string(), rgb: col_title_r },
-        cur_state_line: UiLine { text: format!("Current state: {}", st.engine.cur_state), rgb: col_norm },
+        cur_state_line: UiLine { text: format!("Current state: {}", engine.cur_state), rgb: col_norm },
         fail_line: UiLine { text: format!("Fail link: {}", fail), rgb: col_sub },
         outs_title: UiLine { text: "Outputs at state:".to_string(), rgb: col_sub },
         outs_lines: outs_now.into_iter().map(|o| UiLine { text: format!("• {}", o), rgb: col_out }).collect(),
         recent_title: UiLine { text: "Recent:".to_string(), rgb: col_sub },
-        recent_lines: st.recent_msgs.iter().cloned().map(|m| UiLine { text: m, rgb: col_recent }).collect(),
-        footer: UiLine { text: "Exit: Esc o ctrl-c".to_string(), rgb: col_footer },
+        recent_lines: recent
+            .iter()
+            .map(|m| UiLine { text: m.clone(), rgb: if m.starts_with('✗') { col_missed } else { col_recent } })
+            .collect(),
+        footer: UiLine { text: format!("Facing {side}   Flip: ctrl-f   Exit: Esc o ctrl-c"), rgb: col_footer },
     }
 }
 
@@ -170,9 +262,19 @@ struct TextNode { x: i32, y: i32, line: UiLine }
 #[derive(Clone)]
 struct Scene { bg: (u8, u8, u8), texts: Vec<TextNode> }
 
-fn layout_scene(ui: &UiModel, font_h: i32) -> Scene {
-    let left_x: i32 = 16;
-    let right_x: i32 = 520;
+/* Panels side by side, `PANEL_W` apart. */
+fn layout_scene(panels: &[UiModel], font_h: i32) -> Scene {
+    let texts = panels
+        .iter()
+        .enumerate()
+        .flat_map(|(i, ui)| layout_panel(ui, font_h, i as i32 * PANEL_W))
+        .collect();
+    Scene { bg: (18, 18, 18), texts }
+}
+
+fn layout_panel(ui: &UiModel, font_h: i32, x0: i32) -> Vec<TextNode> {
+    let left_x: i32 = x0 + 16;
+    let right_x: i32 = x0 + 520;
     let h_total: i32 = 600;
     let line_h: i32 = font_h.max(16) + 6;
 
@@ -212,18 +314,26 @@ fn layout_scene(ui: &UiModel, font_h: i32) -> Scene {
 
     texts.push(TextNode { x: right_x - 150, y: h_total - 28, line: ui.footer.clone() });
 
-    Scene { bg: (18, 18, 18), texts }
+    texts
 }
 
 pub fn run_sdl(
     path: &str,
     debug: bool,
-    step_timeout_ms: u64,
+    opts: &EngineOptions,
+    filter: &MoveFilter,
     font_path: &str,
+    record: Option<&str>,
+    json: bool,
 ) -> Result<(), String> {
-    let (cfg, st0) = engine_from_gmr_file(path, Duration::from_millis(step_timeout_ms))?;
+    let (cfg, st0) = versus_from_gmr_file(path, opts)?;
+    let players = cfg.players.len();
+    let mut recording = record.map(|_| Replay::new(cfg.grammar_hash, opts));
 
-    print_engine(&cfg);
+    eprint!("{}", render_all(&cfg.warnings));
+    if !json {
+        print_versus(&cfg, &st0);
+    }
 
     let sdl = sdl2::init().map_err(|e| e.to_string())?;
     let video = sdl.video().map_err(|e| e.to_string())?;
@@ -231,7 +341,7 @@ pub fn run_sdl(
     let font = ttf.load_font(font_path, 18).map_err(|e| e.to_string())?;
 
     let window = video
-        .window("ft_ality (SDL GUI)", 900, 600)
+        .window("ft_ality (SDL GUI)", PANEL_W as u32 * players as u32, 600)
         .position_centered()
         .resizable()
         .build()
@@ -261,9 +371,9 @@ pub fn run_sdl(
         }
     };
 
-    let mut view = ViewState { engine: st0, recent_msgs: VecDeque::new() };
+    let mut view = ViewState { versus: st0, recent_msgs: vec![VecDeque::new(); players] };
     let mut event_pump = sdl.event_pump().map_err(|e| e.to_string())?;
-    let start = Instant::now();
+    let clock = MonotonicClock::new();
 
     'mainloop: loop {
         let mut evs: Vec<AppEvent> = Vec::new();
@@ -273,12 +383,46 @@ pub fn run_sdl(
             }
         }
         let should_quit = evs.iter().any(|e| matches!(e, AppEvent::Quit));
+        evs.push(AppEvent::Tick);
+
+        let now_ms: NowMs = clock.now_ms();
+        view = evs.into_iter().fold(view, |acc, e| {
+            let action = replay_action(&e);
+            let (next, outcomes) = reduce(&cfg, filter, &acc, e, now_ms);
+            if json {
+                let (before, after) = (&acc.versus, &next.versus);
+                match &action {
+                    Some(Action::Key(kind, key)) => {
+                        let ev = InputEvent { key: key.clone(), kind: *kind, time: now_ms };
+                        emit(&key_records(&cfg, before, after, &ev, &outcomes, filter));
+                    }
+                    Some(Action::NextCharacter) => {
+                        emit(&command_records(before, after, "ctrl-n", now_ms));
+                    }
+                    Some(Action::Flip) => {
+                        emit(&command_records(before, after, "ctrl-f", now_ms));
+                    }
+                    None => {
+                        emit(&timer_records(before, after, now_ms, &outcomes, filter));
+                    }
+                }
+            }
+            if let Some(r) = recording.as_mut() {
+                if let Some(action) = action {
+                    r.push_action(now_ms, action);
+                }
+                r.push_moves(now_ms, &outcomes);
+            }
+            next
+        });
 
-        let now_ms: NowMs = start.elapsed().as_millis() as u128;
-        view = evs.into_iter().fold(view, |acc, e| reduce(&cfg, &acc, e, now_ms));
-
-        let ui = build_ui_model(&cfg, &view);
-        let scene = layout_scene(&ui, font.height() as i32);
+        let panels: Vec<UiModel> = (0..players)
+            .map(|i| {
+                let player = (players > 1).then_some(i + 1);
+                build_ui_model(&cfg.players[i], &view.versus.players[i], &view.recent_msgs[i], player, filter)
+            })
+            .collect();
+        let scene = layout_scene(&panels, font.height() as i32);
 
         let (r, g, b) = scene.bg;
         canvas.set_draw_color(Color::RGB(r, g, b));
@@ -289,11 +433,17 @@ pub fn run_sdl(
         canvas.present();
 
         if debug {
-            eprintln!("[state={}]", view.engine.cur_state);
+            let states: Vec<String> = view.versus.players.iter().map(|p| p.cur_state.to_string()).collect();
+            eprintln!("[state={}]", states.join(", "));
         }
 
         if should_quit { break 'mainloop; }
     }
 
+    if let (Some(mut r), Some(file)) = (recording, record) {
+        r.finish(clock.now_ms());
+        r.save(file)?;
+        eprintln!("Session recorded to {file}");
+    }
     Ok(())
 }
@@ -0,0 +1,257 @@
+use std::fmt;
+use std::str::FromStr;
+
+/* `{max_gap=150, tags="air, ex"}` after a move name: key/value pairs, in
+ * order. Values are bare words or double-quoted strings. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attr {
+    pub key: String,
+    pub value: String,
+    /* byte offset of the key inside the parsed text */
+    pub at: usize,
+}
+
+/* Message and byte offset into the parsed text. */
+pub type AttrError = (String, usize);
+
+/* Timing limits of one combo, in ms. Gaps are measured between consecutive
+ * steps, the total from the first step to the last. */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Timing {
+    pub max_gap: Option<u64>,
+    pub min_gap: Option<u64>,
+    pub max_total: Option<u64>,
+}
+
+/* What a move is, beyond its name: `{character=Generic, damage=12,
+ * startup=9, tags=projectile}`. Every field is optional. */
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct MoveInfo {
+    pub character: Option<String>,
+    pub damage: Option<u32>,
+    /* frames before the move hits */
+    pub startup: Option<u32>,
+    pub tags: Vec<String>,
+}
+
+impl MoveInfo {
+    pub fn is_empty(&self) -> bool {
+        *self == MoveInfo::default()
+    }
+}
+
+/* "Generic, 12 dmg, startup 9f, projectile"; empty when nothing is set. */
+impl fmt::Display for MoveInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self
+            .character
+            .iter()
+            .cloned()
+            .chain(self.damage.map(|d| format!("{d} dmg")))
+            .chain(self.startup.map(|s| format!("startup {s}f")))
+            .chain(self.tags.iter().cloned())
+            .collect();
+        f.write_str(&parts.join(", "))
+    }
+}
+
+/* Everything a rule's attribute block sets. */
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct RuleAttrs {
+    pub timing: Timing,
+    /* higher wins under `ResolutionPolicy::HighestPriority` */
+    pub priority: i32,
+    pub info: MoveInfo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Character,
+    Tag,
+    Damage,
+    Startup,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cmp {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Condition {
+    field: Field,
+    cmp: Cmp,
+    value: String,
+}
+
+/* `character=Generic,tag=projectile,damage>=10`: moves whose info meets
+ * every condition. Text fields compare with `=` only; a move without the
+ * field never matches. The empty filter matches everything. */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MoveFilter {
+    conditions: Vec<Condition>,
+}
+
+impl MoveFilter {
+    pub fn matches(&self, info: &MoveInfo) -> bool {
+        self.conditions.iter().all(|c| {
+            let number = |n: Option<u32>| {
+                let (Some(n), Ok(v)) = (n, c.value.parse::<u32>()) else { return false };
+                match c.cmp {
+                    Cmp::Eq => n == v,
+                    Cmp::Lt => n < v,
+                    Cmp::Le => n <= v,
+                    Cmp::Gt => n > v,
+                    Cmp::Ge => n >= v,
+                }
+            };
+            match c.field {
+                Field::Character => info.character.as_deref() == Some(c.value.as_str()),
+                Field::Tag => info.tags.contains(&c.value),
+                Field::Damage => number(info.damage),
+                Field::Startup => number(info.startup),
+            }
+        })
+    }
+}
+
+impl FromStr for MoveFilter {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let condition = |part: &str| -> Result<Condition, String> {
+            let ops = [(">=", Cmp::Ge), ("<=", Cmp::Le), ("=", Cmp::Eq), ("<", Cmp::Lt), (">", Cmp::Gt)];
+            let (at, op, cmp) = ops
+                .iter()
+                .filter_map(|&(op, cmp)| part.find(op).map(|at| (at, op, cmp)))
+                .min_by_key(|&(at, op, _)| (at, std::cmp::Reverse(op.len())))
+                .ok_or_else(|| format!("expected e.g. `tag=projectile` or `damage>=10`, found `{part}`"))?;
+            let (name, value) = (part[..at].trim(), part[at + op.len()..].trim());
+            let field = match name {
+                "character" => Field::Character,
+                "tag" => Field::Tag,
+                "damage" => Field::Damage,
+                "startup" => Field::Startup,
+                _ => return Err(format!("unknown field `{name}` (expected one of: character, tag, damage, startup)")),
+            };
+            let numeric = matches!(field, Field::Damage | Field::Startup);
+            if !numeric && cmp != Cmp::Eq {
+                return Err(format!("`{name}` can only be compared with `=`"));
+            }
+            if numeric && value.parse::<u32>().is_err() {
+                return Err(format!("`{name}` expects a number, found `{value}`"));
+            }
+            Ok(Condition { field, cmp, value: value.to_string() })
+        };
+        let conditions = s.split(',').filter(|p| !p.trim().is_empty()).map(condition).collect::<Result<_, _>>()?;
+        Ok(MoveFilter { conditions })
+    }
+}
+
+impl Timing {
+    pub fn is_empty(&self) -> bool {
+        *self == Timing::default()
+    }
+}
+
+/* "Hadouken {max_gap=150}" -> ("Hadouken", Some("max_gap=150")), with the
+ * byte offset of the block's contents. Without a trailing block the whole
+ * text is the name. */
+pub fn split_attrs(rhs: &str) -> (&str, Option<(&str, usize)>) {
+    match (rhs.trim_end().strip_suffix('}'), rhs.rfind('{')) {
+        (Some(body), Some(open)) if open < body.len() => (rhs[..open].trim_end(), Some((&body[open + 1..], open + 1))),
+        _ => (rhs, None),
+    }
+}
+
+pub fn parse_attrs(src: &str) -> Result<Vec<Attr>, AttrError> {
+    let mut attrs = Vec::new();
+    let mut rest = src;
+    let offset = |s: &str| src.len() - s.len();
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return Ok(attrs);
+        }
+        let at = offset(rest);
+        let eq = rest.find('=').ok_or(("expected `key=value`".to_string(), at))?;
+        let key = rest[..eq].trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err((format!("`{key}` is not a valid attribute name"), at));
+        }
+        if attrs.iter().any(|a: &Attr| a.key == key) {
+            return Err((format!("`{key}` is given twice"), at));
+        }
+        rest = rest[eq + 1..].trim_start();
+
+        let (value, after) = match rest.strip_prefix('"') {
+            Some(quoted) => {
+                let close = quoted.find('"').ok_or(("unterminated string".to_string(), offset(rest)))?;
+                (quoted[..close].to_string(), &quoted[close + 1..])
+            }
+            None => {
+                let end = rest.find(|c: char| c == ',' || c.is_whitespace()).unwrap_or(rest.len());
+                (rest[..end].to_string(), &rest[end..])
+            }
+        };
+        if value.is_empty() {
+            return Err((format!("`{key}` has no value"), at));
+        }
+        attrs.push(Attr { key: key.to_string(), value, at });
+
+        rest = after.trim_start();
+        match rest.strip_prefix(',') {
+            Some(r) => rest = r,
+            None if rest.is_empty() => return Ok(attrs),
+            None => return Err(("expected ',' between attributes".to_string(), offset(rest))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_and_parses_blocks() {
+        assert_eq!(split_attrs("Hadouken {max_gap=150}"), ("Hadouken", Some(("max_gap=150", 10))));
+        assert_eq!(split_attrs("Plain Move"), ("Plain Move", None));
+
+        let attrs = parse_attrs(" max_gap = 150, tags=\"air, ex\" ").unwrap();
+        let pairs: Vec<(&str, &str, usize)> = attrs.iter().map(|a| (a.key.as_str(), a.value.as_str(), a.at)).collect();
+        assert_eq!(pairs, vec![("max_gap", "150", 1), ("tags", "air, ex", 16)]);
+        assert!(parse_attrs("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_blocks() {
+        assert_eq!(parse_attrs("max_gap").unwrap_err(), ("expected `key=value`".to_string(), 0));
+        assert_eq!(parse_attrs("a=1 b=2").unwrap_err().0, "expected ',' between attributes");
+        assert_eq!(parse_attrs("a=1, a=2").unwrap_err(), ("`a` is given twice".to_string(), 5));
+        assert_eq!(parse_attrs("tags=\"x").unwrap_err().0, "unterminated string");
+        assert_eq!(parse_attrs("max gap=1").unwrap_err().0, "`max gap` is not a valid attribute name");
+    }
+
+    #[test]
+    fn filters_match_on_move_info() {
+        let fireball = MoveInfo {
+            character: Some("Generic".to_string()),
+            damage: Some(12),
+            startup: Some(9),
+            tags: vec!["projectile".to_string()],
+        };
+        assert_eq!(fireball.to_string(), "Generic, 12 dmg, startup 9f, projectile");
+
+        let yes = |f: &str| f.parse::<MoveFilter>().unwrap().matches(&fireball);
+        assert!(yes("") && yes("tag=projectile") && yes("character=Generic, damage>=12") && yes("startup<10"));
+        assert!(!yes("tag=low") && !yes("damage>12") && !yes("character=Jax"));
+        assert!(!"damage=1".parse::<MoveFilter>().unwrap().matches(&MoveInfo::default()));
+
+        assert_eq!("tag>x".parse::<MoveFilter>().unwrap_err(), "`tag` can only be compared with `=`");
+        assert_eq!("damage=lots".parse::<MoveFilter>().unwrap_err(), "`damage` expects a number, found `lots`");
+        assert!("speed=3".parse::<MoveFilter>().unwrap_err().starts_with("unknown field `speed`"));
+    }
+}
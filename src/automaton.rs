@@ -1,164 +1,140 @@
-use std::collections::{BTreeMap, BTreeSet};
-use crate::parse::Rule;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use crate::parse::{chord_members, split_step, Rule, StepKind};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 struct Sym(usize);
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct State {
     goto_: BTreeMap<Sym, usize>,
     fail: usize,
     outputs: BTreeSet<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Automaton {
     states: Vec<State>,
     start: usize,
     sym_by_token: BTreeMap<String, Sym>,
-    #[allow(dead_code)]
     token_by_sym: Vec<String>,
+    /* base token -> hold durations the grammar asks for on it, ascending */
+    holds: BTreeMap<String, Vec<u64>>,
 }
 
 fn empty_state() -> State {
     State { goto_: BTreeMap::new(), fail: 0, outputs: BTreeSet::new() }
 }
 
-fn intern_symbol(
-    sym_by_token: &BTreeMap<String, Sym>,
-    token_by_sym: &[String],
-    tok: &str,
-) -> (BTreeMap<String, Sym>, Vec<String>, Sym) {
-    if let Some(&s) = sym_by_token.get(tok) {
-        (sym_by_token.clone(), token_by_sym.to_vec(), s)
-    } else {
-        let s = Sym(token_by_sym.len());
-        let mut new_sym = sym_by_token.clone();
-        let mut new_vec = token_by_sym.to_vec();
-        new_sym.insert(tok.to_string(), s);
-        new_vec.push(tok.to_string());
-        (new_sym, new_vec, s)
+/* "[Back]~800" and "[Back]~300" -> {"[Back]": [300, 800]} */
+fn hold_table(token_by_sym: &[String]) -> BTreeMap<String, Vec<u64>> {
+    let mut holds: BTreeMap<String, BTreeSet<u64>> = BTreeMap::new();
+    for t in token_by_sym {
+        if let (StepKind::Hold(ms), base) = split_step(t) {
+            holds.entry(base.to_string()).or_default().insert(ms);
+        }
     }
+    holds.into_iter().map(|(base, ms)| (base, ms.into_iter().collect())).collect()
 }
 
-fn ensure_edge(states: &[State], from: usize, sym: Sym) -> (Vec<State>, usize) {
-    if let Some(&nxt) = states[from].goto_.get(&sym) {
-        (states.to_vec(), nxt)
-    } else {
-        let new_idx = states.len();
-        let mut new_states = states.to_vec();
-        let mut from_state = new_states[from].clone();
-        let mut goto = from_state.goto_.clone();
-        goto.insert(sym, new_idx);
-        from_state.goto_ = goto;
-        new_states[from] = from_state;
-        /* append new empty state */
-        new_states.push(empty_state());
-        (new_states, new_idx)
-    }
+/* Incremental construction, mutating in place: O(total sequence length)
+ * for the trie, plus one BFS for the failure links. States are numbered in
+ * insertion order, as they always have been. */
+#[derive(Debug, Clone)]
+pub struct AutomatonBuilder {
+    states: Vec<State>,
+    sym_by_token: BTreeMap<String, Sym>,
+    token_by_sym: Vec<String>,
 }
 
-fn add_output(states: &[State], at: usize, out: String) -> Vec<State> {
-    let mut new_states = states.to_vec();
-    let mut st = new_states[at].clone();
-    let mut outs = st.outputs.clone();
-    outs.insert(out);
-    st.outputs = outs;
-    new_states[at] = st;
-    new_states
+impl Default for AutomatonBuilder {
+    fn default() -> Self { Self::new() }
 }
 
-fn build_trie(
-    combos: &[Rule],
-) -> (Vec<State>, BTreeMap<String, Sym>, Vec<String>) {
-    let init_states = vec![empty_state()];
-    combos.iter().fold(
-        (init_states, BTreeMap::new(), Vec::new()),
-        |(states_acc, sym_by_tok_acc, tok_by_sym_acc), r| {
-            let (states_after, sym_map_after, tok_map_after, end_state) =
-                r.sequence.iter().fold(
-                    (states_acc.clone(), sym_by_tok_acc.clone(), tok_by_sym_acc.clone(), 0usize),
-                    |(st, smap, tmap, cur), t| {
-                        let (smap2, tmap2, s) = intern_symbol(&smap, &tmap, t.as_str());
-                        let (st2, nxt) = ensure_edge(&st, cur, s);
-                        (st2, smap2, tmap2, nxt)
-                    },
-                );
-
-            let st_final = add_output(&states_after, end_state, r.move_name.clone());
-            (st_final, sym_map_after, tok_map_after)
-        },
-    )
-}
+impl AutomatonBuilder {
+    pub fn new() -> Self {
+        AutomatonBuilder { states: vec![empty_state()], sym_by_token: BTreeMap::new(), token_by_sym: Vec::new() }
+    }
 
-fn failure_links(states: &[State], start: usize) -> Vec<State> {
-    let root_children: Vec<usize> = states[start].goto_.values().copied().collect();
-    let init_states = root_children.iter().fold(states.to_vec(), |acc, &nxt| {
-        let mut s = acc.clone();
-        let mut st = s[nxt].clone();
-        st.fail = start;
-        s[nxt] = st;
+    fn intern_symbol(&mut self, tok: &str) -> Sym {
+        if let Some(&s) = self.sym_by_token.get(tok) {
+            return s;
+        }
+        let s = Sym(self.token_by_sym.len());
+        self.sym_by_token.insert(tok.to_string(), s);
+        self.token_by_sym.push(tok.to_string());
         s
-    });
-
-    /* Pure BFS using two lists; deterministic because goto_ is a BTreeMap */
-    fn bfs(states: Vec<State>, front: Vec<usize>, back: Vec<usize>, start: usize) -> Vec<State> {
-        match (front.split_first(), back.is_empty()) {
-            (None, true) => states,
-            (None, false) => bfs(states, back.into_iter().rev().collect(), Vec::new(), start),
-            (Some((&r, rest)), _) => {
-                let edges: Vec<(Sym, usize)> =
-                    states[r].goto_.iter().map(|(&sym, &s)| (sym, s)).collect();
-
-                let (states2, back2) = edges.into_iter().fold(
-                    (states.clone(), back.clone()),
-                    |(st_acc, bk_acc), (sym, s)| {
-                        let mut bk2 = bk_acc.clone();
-                        bk2.push(s);
-
-                        /* pure fail-climb helper (recursive) */
-                        fn climb(states: &[State], start: usize, mut f: usize, sym: Sym) -> usize {
-                            /* Tail-recursive style via loop for stack-safety without side effects */
-                            loop {
-                                if f == start || states[f].goto_.contains_key(&sym) {
-                                    break states[f].goto_.get(&sym).copied().unwrap_or(start);
-                                } else {
-                                    f = states[f].fail;
-                                }
-                            }
-                        }
-
-                        let f_next = climb(&st_acc, start, st_acc[r].fail, sym);
-
-                        /* set fail(s) = f_next; outputs(s) ∪= outputs(f_next) */
-                        let mut st_new = st_acc.clone();
-                        let mut s_node = st_new[s].clone();
-                        s_node.fail = f_next;
-
-                        if !st_new[f_next].outputs.is_empty() {
-                            let mut outs = s_node.outputs.clone();
-                            outs.extend(st_new[f_next].outputs.iter().cloned());
-                            s_node.outputs = outs;
-                        }
-
-                        st_new[s] = s_node;
-                        (st_new, bk2)
-                    },
-                );
-
-                bfs(states2, rest.to_vec(), back2, start)
+    }
+
+    fn ensure_edge(&mut self, from: usize, sym: Sym) -> usize {
+        if let Some(&nxt) = self.states[from].goto_.get(&sym) {
+            return nxt;
+        }
+        let new_idx = self.states.len();
+        self.states[from].goto_.insert(sym, new_idx);
+        self.states.push(empty_state());
+        new_idx
+    }
+
+    pub fn add_rule(&mut self, rule: &Rule) -> &mut Self {
+        let end = rule.sequence.iter().fold(0usize, |cur, t| {
+            let sym = self.intern_symbol(t.as_str());
+            self.ensure_edge(cur, sym)
+        });
+        self.states[end].outputs.insert(rule.move_name.clone());
+        self
+    }
+
+    fn failure_links(&mut self, start: usize) {
+        let mut queue: VecDeque<usize> = self.states[start].goto_.values().copied().collect();
+        for &child in &queue {
+            self.states[child].fail = start;
+        }
+
+        /* Level order; goto_ is a BTreeMap so the result is deterministic */
+        while let Some(r) = queue.pop_front() {
+            let edges: Vec<(Sym, usize)> =
+                self.states[r].goto_.iter().map(|(&sym, &s)| (sym, s)).collect();
+            for (sym, s) in edges {
+                queue.push_back(s);
+
+                let mut f = self.states[r].fail;
+                let f_next = loop {
+                    if f == start || self.states[f].goto_.contains_key(&sym) {
+                        break self.states[f].goto_.get(&sym).copied().unwrap_or(start);
+                    }
+                    f = self.states[f].fail;
+                };
+
+                /* fail(s) = f_next; outputs(s) ∪= outputs(f_next) */
+                self.states[s].fail = f_next;
+                if !self.states[f_next].outputs.is_empty() {
+                    let inherited = self.states[f_next].outputs.clone();
+                    self.states[s].outputs.extend(inherited);
+                }
             }
         }
     }
 
-    bfs(init_states, root_children, Vec::new(), start)
+    pub fn build(mut self) -> Automaton {
+        self.failure_links(0);
+        let holds = hold_table(&self.token_by_sym);
+        Automaton {
+            states: self.states,
+            start: 0,
+            sym_by_token: self.sym_by_token,
+            token_by_sym: self.token_by_sym,
+            holds,
+        }
+    }
 }
 
 impl Automaton {
     pub fn from_combos(combos: &[Rule]) -> Self {
-        let (trie_states, sym_by_token, token_by_sym) = build_trie(combos);
-        let states = failure_links(&trie_states, 0);
-        Automaton { states, start: 0, sym_by_token, token_by_sym }
+        let mut builder = AutomatonBuilder::new();
+        for r in combos {
+            builder.add_rule(r);
+        }
+        builder.build()
     }
 
     /* (cur, tok) → (next, outputs). */
@@ -180,6 +156,46 @@ impl Automaton {
         }
     }
 
+    pub fn num_states(&self) -> usize { self.states.len() }
+    pub fn start(&self) -> usize { self.start }
+
+    /* Interned tokens; a symbol's id is its index here. */
+    pub fn symbols(&self) -> &[String] { &self.token_by_sym }
+
+    /* Trie edges out of `state` as (symbol id, target). */
+    pub fn goto_edges(&self, state: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.states[state].goto_.iter().map(|(&Sym(sym), &nxt)| (sym, nxt))
+    }
+
+    /* Trie node spelled by `tokens` from the start (goto edges only). */
+    pub fn trie_state<S: AsRef<str>>(&self, tokens: &[S]) -> Option<usize> {
+        tokens.iter().try_fold(self.start, |cur, t| {
+            let sym = self.sym_by_token.get(t.as_ref())?;
+            self.states[cur].goto_.get(sym).copied()
+        })
+    }
+
+    pub fn has_symbol(&self, internal_tok: &str) -> bool {
+        self.sym_by_token.contains_key(internal_tok)
+    }
+
+    /* Members of every chord symbol, e.g. [["[BP]", "[FP]"]]. */
+    pub fn chord_symbols(&self) -> Vec<Vec<&str>> {
+        self.token_by_sym
+            .iter()
+            .map(|t| chord_members(t))
+            .filter(|m| m.len() > 1)
+            .collect()
+    }
+
+    /* Hold durations the grammar asks for on `internal`, ascending. */
+    pub fn hold_thresholds(&self, internal: &str) -> &[u64] {
+        self.holds.get(internal).map_or(&[], Vec::as_slice)
+    }
+
+    /* Failure link of `state`. */
+    pub fn fail(&self, state: usize) -> usize { self.states[state].fail }
+
     pub fn state_info(&self, idx: usize) -> (Vec<String>, usize) {
         (self.states[idx].outputs.iter().cloned().collect(), self.states[idx].fail)
     }
@@ -191,3 +207,95 @@ impl Automaton {
             .unwrap_or_default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{classify, parse_gmr, parse_gmr_file};
+
+    /* (outputs, failure link) of every state, in state order */
+    fn links(a: &Automaton) -> Vec<(Vec<String>, usize)> {
+        (0..a.num_states()).map(|s| a.state_info(s)).collect()
+    }
+
+    fn edges(a: &Automaton) -> Vec<(usize, &str, usize)> {
+        (0..a.num_states())
+            .flat_map(|s| a.goto_edges(s).map(move |(sym, nxt)| (s, a.symbols()[sym].as_str(), nxt)))
+            .collect()
+    }
+
+    fn outs(names: &[&str], fail: usize) -> (Vec<String>, usize) {
+        (names.iter().map(|n| n.to_string()).collect(), fail)
+    }
+
+    #[test]
+    fn builds_the_test_grammar() {
+        let compiled = classify(&parse_gmr_file("grammar/test.gmr").unwrap());
+        let a = Automaton::from_combos(&compiled.combos);
+        assert_eq!(a.symbols(), ["[BP]", "[FP]", "[E]", "[Down]", "[Right]", "[Left]", "[BK]"]);
+        assert_eq!(edges(&a), vec![
+            (0, "[BP]", 1), (0, "[FP]", 2), (0, "[Down]", 6), (0, "[Left]", 9),
+            (1, "[FP]", 4),
+            (2, "[FP]", 3),
+            (4, "[E]", 5),
+            (6, "[Right]", 7),
+            (7, "[FP]", 8),
+            (9, "[Left]", 10),
+            (10, "[BK]", 11),
+        ]);
+        assert_eq!(links(&a), vec![
+            outs(&[], 0),
+            outs(&["Claw Slam (Freddy Krueger)"], 0),
+            outs(&[], 0),
+            outs(&["Test state"], 2),
+            outs(&["Active Duty (Jax)"], 2),
+            outs(&["Maxi combo"], 0),
+            outs(&[], 0),
+            outs(&[], 0),
+            outs(&["Fireball (Generic)"], 2),
+            outs(&[], 0),
+            outs(&[], 9),
+            outs(&["Slide (Generic)", "Slide2 (Generic)"], 0),
+        ]);
+    }
+
+    #[test]
+    fn builds_overlapping_suffixes() {
+        let src = "
+            [A], [B], [A], [B], [C] -> Long
+            [B], [A], [B] -> Mid
+            [C], [A] -> Other
+            [A], [B] -> Short
+            [B] -> Tiny
+            [A], [B] -> Short Again
+        ";
+        let compiled = classify(&parse_gmr(src).unwrap());
+        let a = Automaton::from_combos(&compiled.combos);
+        assert_eq!(edges(&a), vec![
+            (0, "[A]", 1), (0, "[B]", 6), (0, "[C]", 9),
+            (1, "[B]", 2),
+            (2, "[A]", 3),
+            (3, "[B]", 4),
+            (4, "[C]", 5),
+            (6, "[A]", 7),
+            (7, "[B]", 8),
+            (9, "[A]", 10),
+        ]);
+        /* outputs include those of every suffix, through the failure links */
+        let short = ["Short", "Short Again", "Tiny"];
+        let mid = ["Mid", "Short", "Short Again", "Tiny"];
+        assert_eq!(links(&a), vec![
+            outs(&[], 0),
+            outs(&[], 0),
+            outs(&short, 6),
+            outs(&[], 7),
+            outs(&mid, 8),
+            outs(&["Long"], 9),
+            outs(&["Tiny"], 0),
+            outs(&[], 1),
+            outs(&mid, 2),
+            outs(&[], 0),
+            outs(&["Other"], 1),
+        ]);
+    }
+}
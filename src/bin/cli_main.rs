@@ -1,23 +1,155 @@
 use std::env;
-use ft_ality::apps::cli::run_cli;
+use std::io::{self, IsTerminal};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::time::Duration;
+use ft_ality::apps::cli::{run_batch, run_check, run_cli, run_dot, run_replay_file, run_tests};
+use ft_ality::attrs::MoveFilter;
+use ft_ality::dot::DotOptions;
+use ft_ality::engine::{EngineOptions, Pinned};
 
-fn main() -> Result<(), String> {
+/* batch mode ran fine, but no move came out of it */
+const NOTHING_RECOGNISED: u8 = 3;
+
+const USAGE: &str = "usage: cli <file.gmr> [--debug] [--timeout-ms=N] [--chord-ms=N] [--strict] [--expand-macros] [--deferred]\n                      \
+                     [--on-timeout=reset|failure_link|keep] [--on-unknown-key=reset|ignore|noise]\n                      \
+                     [--resolution=all|highest_priority|longest_match|first_declared]\n                      \
+                     [--filter=FIELD=VALUE,...] (character, tag, damage, startup; e.g. damage>=10)\n                      \
+                     [--record=FILE] [--batch[=FILE]] (key tokens from FILE or stdin, each `tok` or `tok@ms`;\n                      \
+                      implied when stdin is not a terminal; exits 3 if no move was recognised, 2 on error)\n                      \
+                     [--json] (one JSON object per event on stdout; see README.md)\n       \
+                     cli check <file.gmr>\n       \
+                     cli replay <file.gmr> <file.replay>\n       \
+                     cli test <file.gmr> <file.gmrtest>...\n       \
+                     cli dot <file.gmr> [--no-root-fail] [--highlight=TOK,TOK,...] [--character=NAME] [--out=FILE]";
+
+/* The value of `flag`, or why it is not one, followed by the usage. */
+fn flag_value<T: FromStr<Err = String>>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|e| format!("invalid {flag} value: {e}\n{USAGE}"))
+}
+
+fn millis(flag: &str, value: &str) -> Result<Duration, String> {
+    value
+        .parse()
+        .map(Duration::from_millis)
+        .map_err(|_| format!("invalid {flag} value: expected milliseconds, found `{value}`\n{USAGE}"))
+}
+
+fn dot_main(args: &[String]) -> Result<(), String> {
+    let path = args.first().ok_or(USAGE)?;
+    let (opts, character, out) = args.iter().skip(1).fold(
+        (DotOptions::default(), None, None),
+        |(opts, character, out), arg| {
+            if arg == "--no-root-fail" {
+                (DotOptions { hide_root_failures: true, ..opts }, character, out)
+            } else if let Some(seq) = arg.strip_prefix("--highlight=") {
+                let highlight = seq.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect();
+                (DotOptions { highlight, ..opts }, character, out)
+            } else if let Some(name) = arg.strip_prefix("--character=") {
+                (opts, Some(name.to_string()), out)
+            } else if let Some(file) = arg.strip_prefix("--out=") {
+                (opts, character, Some(file.to_string()))
+            } else {
+                (opts, character, out)
+            }
+        },
+    );
+    run_dot(path, &opts, character.as_deref(), out.as_deref())
+}
+
+/* Exit status: 0 on success, 1 on failure. In batch mode: 0 if a move was
+ * recognised, NOTHING_RECOGNISED if none was, 2 on error (including
+ * interactive-only flags such as --record). */
+fn try_main() -> Result<ExitCode, String> {
     let args: Vec<String> = env::args().skip(1).collect();
 
+    let done = match args.first().map(String::as_str) {
+        Some("dot") => Some(dot_main(&args[1..])),
+        Some("check") => Some(run_check(args.get(1).ok_or(USAGE)?)),
+        Some("test") if args.len() >= 3 => Some(run_tests(&args[1], &args[2..])),
+        Some("test") => return Err(USAGE.to_string()),
+        Some("replay") => Some(run_replay_file(args.get(1).ok_or(USAGE)?, args.get(2).ok_or(USAGE)?)),
+        _ => None,
+    };
+    if let Some(result) = done {
+        return result.map(|()| ExitCode::SUCCESS);
+    }
+
     let path = args.first()
-        .ok_or("usage: cli <file.gmr> [--debug] [--timeout-ms N]")?
+        .ok_or(USAGE)?
         .clone();
 
-    let (debug, timeout_ms) = args.iter().skip(1).fold((false, 500), |(debug, timeout_ms), arg| {
-        if arg == "--debug" || arg == "-d" {
-            (true, timeout_ms)
+    let (debug, opts) = args.iter().skip(1).try_fold((false, EngineOptions::default()), |(debug, opts), arg| {
+        Ok::<_, String>(if arg == "--debug" || arg == "-d" {
+            (true, opts)
         } else if let Some(ms) = arg.strip_prefix("--timeout-ms=") {
-            let parsed_ms = ms.parse().expect("invalid --timeout-ms value");
-            (debug, parsed_ms)
+            let step_timeout = millis("--timeout-ms", ms)?;
+            let pinned = Pinned { step_timeout: true, ..opts.pinned };
+            (debug, EngineOptions { step_timeout, pinned, ..opts })
+        } else if let Some(ms) = arg.strip_prefix("--chord-ms=") {
+            let chord_window = millis("--chord-ms", ms)?;
+            (debug, EngineOptions { chord_window, ..opts })
+        } else if arg == "--strict" {
+            (debug, EngineOptions { strict: true, ..opts })
+        } else if arg == "--expand-macros" {
+            (debug, EngineOptions { expand_macros: true, ..opts })
+        } else if arg == "--deferred" {
+            (debug, EngineOptions { deferred: true, ..opts })
+        } else if let Some(policy) = arg.strip_prefix("--resolution=") {
+            let resolution = flag_value("--resolution", policy)?;
+            let pinned = Pinned { resolution: true, ..opts.pinned };
+            (debug, EngineOptions { resolution, pinned, ..opts })
+        } else if let Some(policy) = arg.strip_prefix("--on-timeout=") {
+            let on_timeout = flag_value("--on-timeout", policy)?;
+            let pinned = Pinned { on_timeout: true, ..opts.pinned };
+            (debug, EngineOptions { on_timeout, pinned, ..opts })
+        } else if let Some(policy) = arg.strip_prefix("--on-unknown-key=") {
+            let on_unknown_key = flag_value("--on-unknown-key", policy)?;
+            let pinned = Pinned { on_unknown_key: true, ..opts.pinned };
+            (debug, EngineOptions { on_unknown_key, pinned, ..opts })
         } else {
-            (debug, timeout_ms)
-        }
-    });
+            (debug, opts)
+        })
+    })?;
+
+    let filter: MoveFilter = match args.iter().find_map(|a| a.strip_prefix("--filter=")) {
+        Some(f) => f.parse().map_err(|e| format!("invalid --filter value: {e}"))?,
+        None => MoveFilter::default(),
+    };
+
+    let record = args.iter().find_map(|a| a.strip_prefix("--record="));
+    let json = args.iter().any(|a| a == "--json");
 
-    run_cli(&path, debug, timeout_ms)
+    /* piped input means batch mode, even without the flag */
+    let batch = args
+        .iter()
+        .find_map(|a| if a == "--batch" { Some("-") } else { a.strip_prefix("--batch=") })
+        .or_else(|| (!io::stdin().is_terminal()).then_some("-"));
+
+    /* --record saves what the terminal session typed; a batch has no session */
+    let batch_run = |source| match record {
+        Some(_) => Err("--record is only available in interactive mode (stdin is not a terminal, or --batch was given)".to_string()),
+        None => run_batch(&path, debug, &opts, &filter, source, json),
+    };
+    match batch {
+        Some(source) => Ok(match batch_run(source) {
+            Ok(0) => ExitCode::from(NOTHING_RECOGNISED),
+            Ok(_) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{}", e.trim_end());
+                ExitCode::from(2)
+            }
+        }),
+        None => run_cli(&path, debug, &opts, &filter, record, json).map(|()| ExitCode::SUCCESS),
+    }
+}
+
+fn main() -> ExitCode {
+    match try_main() {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e.trim_end());
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -1,29 +1,85 @@
 #![cfg(feature = "sdl")]
 
 use std::env;
+use std::str::FromStr;
+use std::time::Duration;
 use ft_ality::apps::sdl::run_sdl;
+use ft_ality::attrs::MoveFilter;
+use ft_ality::engine::{EngineOptions, Pinned};
 
-fn main() -> Result<(), String> {
+const USAGE: &str = "usage: sdl <file.gmr> [--debug] [--timeout-ms=N] [--chord-ms=N] [--strict] [--expand-macros] [--deferred]\n                      \
+                     [--on-timeout=reset|failure_link|keep] [--on-unknown-key=reset|ignore|noise]\n                      \
+                     [--resolution=all|highest_priority|longest_match|first_declared]\n                      \
+                     [--filter=FIELD=VALUE,...] [--font=PATH] [--record=FILE] [--json]";
+
+/* The value of `flag`, or why it is not one, followed by the usage. */
+fn flag_value<T: FromStr<Err = String>>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|e| format!("invalid {flag} value: {e}\n{USAGE}"))
+}
+
+fn millis(flag: &str, value: &str) -> Result<Duration, String> {
+    value
+        .parse()
+        .map(Duration::from_millis)
+        .map_err(|_| format!("invalid {flag} value: expected milliseconds, found `{value}`\n{USAGE}"))
+}
+
+fn try_main() -> Result<(), String> {
     let args: Vec<String> = env::args().skip(1).collect();
-    let path = args.first()
-        .ok_or("usage: cli <file.gmr> [--debug] [--timeout-ms N]")?
-        .clone();
-
-    let (debug, timeout_ms, font_path) = args.iter().skip(1).fold(
-        (false, 500, "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf".to_string()),
-        |(debug, timeout_ms, font_path), arg| {
-            if arg == "--debug" || arg == "-d" {
-                (true, timeout_ms, font_path)
+    let path = args.first().ok_or(USAGE)?.clone();
+
+    let (debug, opts, font_path) = args.iter().skip(1).try_fold(
+        (false, EngineOptions::default(), "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf".to_string()),
+        |(debug, opts, font_path), arg| {
+            Ok::<_, String>(if arg == "--debug" || arg == "-d" {
+                (true, opts, font_path)
             } else if let Some(ms) = arg.strip_prefix("--timeout-ms=") {
-                let parsed_ms = ms.parse().expect("invalid --timeout-ms value");
-                (debug, parsed_ms, font_path)
+                let step_timeout = millis("--timeout-ms", ms)?;
+                let pinned = Pinned { step_timeout: true, ..opts.pinned };
+                (debug, EngineOptions { step_timeout, pinned, ..opts }, font_path)
+            } else if let Some(ms) = arg.strip_prefix("--chord-ms=") {
+                let chord_window = millis("--chord-ms", ms)?;
+                (debug, EngineOptions { chord_window, ..opts }, font_path)
+            } else if arg == "--strict" {
+                (debug, EngineOptions { strict: true, ..opts }, font_path)
+            } else if arg == "--expand-macros" {
+                (debug, EngineOptions { expand_macros: true, ..opts }, font_path)
+            } else if arg == "--deferred" {
+                (debug, EngineOptions { deferred: true, ..opts }, font_path)
+            } else if let Some(policy) = arg.strip_prefix("--resolution=") {
+                let resolution = flag_value("--resolution", policy)?;
+                let pinned = Pinned { resolution: true, ..opts.pinned };
+                (debug, EngineOptions { resolution, pinned, ..opts }, font_path)
+            } else if let Some(policy) = arg.strip_prefix("--on-timeout=") {
+                let on_timeout = flag_value("--on-timeout", policy)?;
+                let pinned = Pinned { on_timeout: true, ..opts.pinned };
+                (debug, EngineOptions { on_timeout, pinned, ..opts }, font_path)
+            } else if let Some(policy) = arg.strip_prefix("--on-unknown-key=") {
+                let on_unknown_key = flag_value("--on-unknown-key", policy)?;
+                let pinned = Pinned { on_unknown_key: true, ..opts.pinned };
+                (debug, EngineOptions { on_unknown_key, pinned, ..opts }, font_path)
             } else if let Some(fp) = arg.strip_prefix("--font=") {
-                (debug, timeout_ms, fp.to_string())
+                (debug, opts, fp.to_string())
             } else {
-                (debug, timeout_ms, font_path)
-            }
+                (debug, opts, font_path)
+            })
         },
-    );
+    )?;
+
+    let filter: MoveFilter = match args.iter().find_map(|a| a.strip_prefix("--filter=")) {
+        Some(f) => f.parse().map_err(|e| format!("invalid --filter value: {e}"))?,
+        None => MoveFilter::default(),
+    };
+
+    let record = args.iter().find_map(|a| a.strip_prefix("--record="));
+    let json = args.iter().any(|a| a == "--json");
+
+    run_sdl(&path, debug, &opts, &filter, &font_path, record, json)
+}
 
-    run_sdl(&path, debug, timeout_ms, &font_path)
+fn main() {
+    if let Err(e) = try_main() {
+        eprintln!("{}", e.trim_end());
+        std::process::exit(1);
+    }
 }
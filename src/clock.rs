@@ -0,0 +1,116 @@
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/* Source of "now" in milliseconds, shared by the engine and every frontend.
+ * Only differences between two readings matter, so any origin is fine. */
+pub trait Clock {
+    fn now_ms(&self) -> u128;
+}
+
+/* Wall clock: milliseconds elapsed since the clock was created. */
+#[derive(Debug, Clone, Copy)]
+pub struct MonotonicClock {
+    start: Instant,
+}
+
+impl MonotonicClock {
+    pub fn new() -> Self {
+        MonotonicClock { start: Instant::now() }
+    }
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self { Self::new() }
+}
+
+impl Clock for MonotonicClock {
+    fn now_ms(&self) -> u128 {
+        self.start.elapsed().as_millis()
+    }
+}
+
+/* Fake clock for tests: time only moves when told to. */
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: Cell<u128>,
+}
+
+impl ManualClock {
+    pub fn new(start_ms: u128) -> Self {
+        ManualClock { now: Cell::new(start_ms) }
+    }
+
+    pub fn set(&self, now_ms: u128) {
+        self.now.set(now_ms);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.set(self.now.get() + by.as_millis());
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_ms(&self) -> u128 {
+        self.now.get()
+    }
+}
+
+/* Frame-counting clock: now = frames * frame duration.
+ * Makes timing depend on rendered frames instead of wall time (e.g. 60 fps -> 16ms). */
+#[derive(Debug)]
+pub struct FrameClock {
+    frames: Cell<u64>,
+    frame_ms: u128,
+}
+
+impl FrameClock {
+    pub fn new(frame: Duration) -> Self {
+        FrameClock { frames: Cell::new(0), frame_ms: frame.as_millis() }
+    }
+
+    pub fn tick(&self) {
+        self.frames.set(self.frames.get() + 1);
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames.get()
+    }
+}
+
+impl Clock for FrameClock {
+    fn now_ms(&self) -> u128 {
+        self.frames.get() as u128 * self.frame_ms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock_moves_only_when_told() {
+        let clock = ManualClock::new(10);
+        assert_eq!(clock.now_ms(), 10);
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(clock.now_ms(), 260);
+        clock.set(5);
+        assert_eq!(clock.now_ms(), 5);
+    }
+
+    #[test]
+    fn frame_clock_counts_frames() {
+        let clock = FrameClock::new(Duration::from_millis(16));
+        assert_eq!(clock.now_ms(), 0);
+        (0..3).for_each(|_| clock.tick());
+        assert_eq!(clock.frames(), 3);
+        assert_eq!(clock.now_ms(), 48);
+    }
+
+    #[test]
+    fn monotonic_clock_never_goes_back() {
+        let clock = MonotonicClock::new();
+        let a = clock.now_ms();
+        let b = clock.now_ms();
+        assert!(b >= a);
+    }
+}
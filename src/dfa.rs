@@ -0,0 +1,160 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+
+use crate::automaton::Automaton;
+
+/* Index of an automaton symbol; see `Dfa::symbol`. */
+pub type SymbolId = usize;
+/* Index of a move name; see `Dfa::output_name`. */
+pub type MoveId = usize;
+
+/* Dense transition table compiled from the Aho-Corasick automaton:
+ * failure links are folded into the table, so a step is one lookup.
+ * Tokens are looked up once, up front; stepping only deals in ids. */
+#[derive(Debug, Clone)]
+pub struct Dfa {
+    /* symbols plus the `unknown` column */
+    width: usize,
+    start: usize,
+    /* state * width + sym -> next state */
+    table: Vec<usize>,
+    sym_by_token: HashMap<String, usize>,
+    /* outputs of state s are out_ids[out_start[s]..out_start[s + 1]] */
+    out_start: Vec<usize>,
+    out_ids: Vec<usize>,
+    output_names: Vec<String>,
+}
+
+impl Dfa {
+    pub fn from_automaton(a: &Automaton) -> Self {
+        let num_states = a.num_states();
+        let width = a.symbols().len() + 1;
+        let start = a.start();
+        let mut table = vec![start; num_states * width];
+
+        /* BFS so that fail(s), being shallower, is filled before s */
+        let mut queue: VecDeque<usize> = VecDeque::from([start]);
+        while let Some(s) = queue.pop_front() {
+            let (_, fail) = a.state_info(s);
+            if s != start {
+                let (dst, src) = (s * width, fail * width);
+                table.copy_within(src..src + width, dst);
+            }
+            for (sym, nxt) in a.goto_edges(s) {
+                table[s * width + sym] = nxt;
+                queue.push_back(nxt);
+            }
+        }
+
+        /* ids follow name order, so per-state output lists keep the
+         * automaton's (alphabetical) order */
+        let output_names: Vec<String> = (0..num_states)
+            .flat_map(|s| a.outputs_at(s))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let id_of: HashMap<&str, usize> =
+            output_names.iter().enumerate().map(|(i, n)| (n.as_str(), i)).collect();
+
+        let mut out_start = Vec::with_capacity(num_states + 1);
+        let mut out_ids = Vec::new();
+        for s in 0..num_states {
+            out_start.push(out_ids.len());
+            out_ids.extend(a.outputs_at(s).iter().map(|n| id_of[n.as_str()]));
+        }
+        out_start.push(out_ids.len());
+
+        let sym_by_token =
+            a.symbols().iter().enumerate().map(|(i, t)| (t.clone(), i)).collect();
+
+        Dfa { width, start, table, sym_by_token, out_start, out_ids, output_names }
+    }
+
+    pub fn start(&self) -> usize { self.start }
+    pub fn num_states(&self) -> usize { self.out_start.len() - 1 }
+
+    pub fn symbol(&self, internal_tok: &str) -> Option<SymbolId> {
+        self.sym_by_token.get(internal_tok).copied()
+    }
+
+    /* A symbol no combo uses: every state steps back to start on it. */
+    pub fn unknown(&self) -> SymbolId { self.width - 1 }
+
+    #[inline]
+    pub fn next(&self, cur: usize, sym: SymbolId) -> usize {
+        self.table[cur * self.width + sym]
+    }
+
+    #[inline]
+    pub fn outputs(&self, state: usize) -> &[MoveId] {
+        &self.out_ids[self.out_start[state]..self.out_start[state + 1]]
+    }
+
+    pub fn output_name(&self, id: MoveId) -> &str { &self.output_names[id] }
+    pub fn num_outputs(&self) -> usize { self.output_names.len() }
+
+    /* Same contract as `Automaton::step`, on ids. */
+    #[inline]
+    pub fn step(&self, cur: usize, sym: SymbolId) -> (usize, &[MoveId]) {
+        let next = self.next(cur, sym);
+        (next, self.outputs(next))
+    }
+}
+
+impl Automaton {
+    pub fn compile(&self) -> Dfa {
+        Dfa::from_automaton(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{classify, parse_gmr, parse_gmr_file};
+
+    /* Small LCG so the comparison is reproducible without extra crates. */
+    fn pseudo_random(seed: u64) -> impl Iterator<Item = u64> {
+        std::iter::successors(Some(seed), |x| {
+            Some(x.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407))
+        })
+        .map(|x| x >> 33)
+    }
+
+    fn assert_same_runs(a: &Automaton, alphabet: &[String], steps: usize, seed: u64) {
+        let dfa = a.compile();
+        assert_eq!(dfa.num_states(), a.num_states());
+        let (mut sa, mut sd) = (a.start(), dfa.start());
+        for r in pseudo_random(seed).take(steps) {
+            let tok = &alphabet[r as usize % alphabet.len()];
+            let (na, oa) = a.step(sa, tok);
+            let (nd, od) = dfa.step(sd, dfa.symbol(tok).unwrap_or(dfa.unknown()));
+            let od: Vec<String> = od.iter().map(|&id| dfa.output_name(id).to_string()).collect();
+            assert_eq!((na, &oa), (nd, &od), "diverged on {tok} from state {sa}");
+            (sa, sd) = (na, nd);
+        }
+    }
+
+    #[test]
+    fn matches_reference_on_test_grammar() {
+        let compiled = classify(&parse_gmr_file("grammar/test.gmr").unwrap());
+        let a = Automaton::from_combos(&compiled.combos);
+        let mut alphabet = compiled.internal_alphabet.clone();
+        alphabet.push("[Unknown]".to_string());
+        assert_same_runs(&a, &alphabet, 5_000, 42);
+    }
+
+    #[test]
+    fn matches_reference_with_overlapping_suffixes() {
+        let src = "
+            [A], [B], [A], [B], [C] -> Long
+            [B], [A], [B] -> Mid
+            [A], [B] -> Short
+            [B] -> Tiny
+            [A]+[C], [B] -> Chord
+        ";
+        let compiled = classify(&parse_gmr(src).unwrap());
+        let a = Automaton::from_combos(&compiled.combos);
+        let alphabet: Vec<String> =
+            ["[A]", "[B]", "[C]", "[A]+[C]"].iter().map(|s| s.to_string()).collect();
+        assert_same_runs(&a, &alphabet, 5_000, 7);
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/* Where something came from: 1-based line, 1-based char column and a
+ * width in chars, plus the whole source line for snippets. */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceLoc {
+    pub file: Option<String>,
+    pub line: usize,
+    pub col: usize,
+    pub len: usize,
+    pub text: String,
+}
+
+impl SourceLoc {
+    /* Location of the byte range `span` inside `text`. */
+    pub fn new(file: Option<&str>, line: usize, text: &str, span: std::ops::Range<usize>) -> Self {
+        let col = text[..span.start].chars().count() + 1;
+        let len = text[span].chars().count().max(1);
+        SourceLoc { file: file.map(str::to_string), line, col, len, text: text.to_string() }
+    }
+
+    /* The non-blank part of the line. */
+    pub fn whole_line(file: Option<&str>, line: usize, text: &str) -> Self {
+        let start = text.len() - text.trim_start().len();
+        let end = text.trim_end().len().max(start);
+        Self::new(file, line, text, start..end)
+    }
+}
+
+impl fmt::Display for SourceLoc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{file}:{}:{}", self.line, self.col),
+            None => write!(f, "{}:{}", self.line, self.col),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub loc: SourceLoc,
+}
+
+impl Diagnostic {
+    pub fn new<S: Into<String>>(severity: Severity, loc: SourceLoc, message: S) -> Self {
+        Diagnostic { severity, message: message.into(), loc }
+    }
+
+    pub fn error<S: Into<String>>(loc: SourceLoc, message: S) -> Self {
+        Self::new(Severity::Error, loc, message)
+    }
+
+    pub fn warning<S: Into<String>>(loc: SourceLoc, message: S) -> Self {
+        Self::new(Severity::Warning, loc, message)
+    }
+
+    pub fn is_error(&self) -> bool { self.severity == Severity::Error }
+
+    /*
+     * error: expected '->' in rule
+     *  --> grammar/errors/missing_arrow.gmr:6:1
+     *   |
+     * 6 | BP, FP > Active Duty (Jax)
+     *   | ^^^^^^^^^^^^^^^^^^^^^^^^^^
+     */
+    pub fn render(&self) -> String {
+        let line_no = self.loc.line.to_string();
+        let pad = " ".repeat(line_no.len());
+        let text = self.loc.text.trim_end();
+        let caret_pad = " ".repeat(self.loc.col.saturating_sub(1));
+        let carets = "^".repeat(self.loc.len.max(1));
+        format!(
+            "{}: {}\n{pad}--> {}\n{pad} |\n{line_no} | {text}\n{pad} | {caret_pad}{carets}\n",
+            self.severity, self.message, self.loc,
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.render())
+    }
+}
+
+pub fn render_all(diags: &[Diagnostic]) -> String {
+    diags.iter().map(Diagnostic::render).collect::<Vec<_>>().join("\n")
+}
+
+pub fn has_errors(diags: &[Diagnostic]) -> bool {
+    diags.iter().any(Diagnostic::is_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_like_rustc() {
+        let text = "BP, FP > Active Duty (Jax)";
+        let d = Diagnostic::error(SourceLoc::whole_line(Some("moves.gmr"), 6, text), "expected '->' in rule");
+        assert_eq!(
+            d.render(),
+            "error: expected '->' in rule\n --> moves.gmr:6:1\n  |\n6 | BP, FP > Active Duty (Jax)\n  | ^^^^^^^^^^^^^^^^^^^^^^^^^^\n",
+        );
+    }
+
+    #[test]
+    fn columns_count_chars_not_bytes() {
+        let text = "  [é], [X] -> Move";
+        let start = text.find("[X]").unwrap();
+        let loc = SourceLoc::new(None, 2, text, start..start + 3);
+        assert_eq!((loc.col, loc.len), (8, 3));
+        assert!(Diagnostic::warning(loc, "w").render().ends_with("  |        ^^^\n"));
+    }
+}
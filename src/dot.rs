@@ -0,0 +1,150 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::automaton::Automaton;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DotOptions {
+    /* omit the dashed edges that just point back to the start state */
+    pub hide_root_failures: bool,
+    /* internal tokens whose run from the start state is drawn in red */
+    pub highlight: Vec<String>,
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/* States and edges visited when feeding an input sequence. */
+#[derive(Debug, Default)]
+struct Trace {
+    nodes: BTreeSet<usize>,
+    /* (from, to, sym) */
+    gotos: BTreeSet<(usize, usize, usize)>,
+    /* (from, fail) */
+    fails: BTreeSet<(usize, usize)>,
+}
+
+fn trace(a: &Automaton, input: &[String]) -> Trace {
+    let start = a.start();
+    let mut nodes = BTreeSet::from([start]);
+    let mut gotos = BTreeSet::new();
+    let mut fails = BTreeSet::new();
+    let mut cur = start;
+    for tok in input {
+        let Some(sym) = a.symbols().iter().position(|t| t == tok) else {
+            cur = start;
+            nodes.insert(cur);
+            continue;
+        };
+        loop {
+            if let Some((_, nxt)) = a.goto_edges(cur).find(|&(s, _)| s == sym) {
+                gotos.insert((cur, nxt, sym));
+                cur = nxt;
+                break;
+            }
+            if cur == start {
+                break;
+            }
+            let (_, fail) = a.state_info(cur);
+            fails.insert((cur, fail));
+            cur = fail;
+        }
+        nodes.insert(cur);
+    }
+    Trace { nodes, gotos, fails }
+}
+
+impl Automaton {
+    pub fn to_dot(&self) -> String {
+        self.to_dot_with(&DotOptions::default())
+    }
+
+    pub fn to_dot_with(&self, opts: &DotOptions) -> String {
+        let start = self.start();
+        let hl = if opts.highlight.is_empty() { Trace::default() } else { trace(self, &opts.highlight) };
+        let red = |on: bool| if on { ", color=red, penwidth=2" } else { "" };
+
+        let mut out = String::new();
+        out.push_str("digraph automaton {\n");
+        out.push_str("    rankdir=LR;\n");
+        out.push_str("    node [shape=circle, fontname=\"monospace\"];\n");
+        out.push_str("    edge [fontname=\"monospace\"];\n");
+
+        for s in 0..self.num_states() {
+            let (outputs, _) = self.state_info(s);
+            let shape = if outputs.is_empty() { "circle" } else { "doublecircle" };
+            let label = std::iter::once(s.to_string())
+                .chain(outputs.iter().map(|o| escape(o)))
+                .collect::<Vec<_>>()
+                .join("\\n");
+            let style = if s == start { ", style=bold" } else { "" };
+            let _ = writeln!(out, "    {s} [label=\"{label}\", shape={shape}{style}{}];", red(hl.nodes.contains(&s)));
+        }
+
+        for s in 0..self.num_states() {
+            for (sym, nxt) in self.goto_edges(s) {
+                let label = escape(&self.symbols()[sym]);
+                let _ = writeln!(out, "    {s} -> {nxt} [label=\"{label}\"{}];", red(hl.gotos.contains(&(s, nxt, sym))));
+            }
+        }
+
+        for s in (0..self.num_states()).filter(|&s| s != start) {
+            let (_, fail) = self.state_info(s);
+            if fail == start && opts.hide_root_failures {
+                continue;
+            }
+            let color = if hl.fails.contains(&(s, fail)) { "red, penwidth=2" } else { "gray" };
+            let _ = writeln!(out, "    {s} -> {fail} [style=dashed, arrowhead=empty, color={color}];");
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{classify, parse_gmr};
+
+    fn automaton(src: &str) -> Automaton {
+        Automaton::from_combos(&classify(&parse_gmr(src).unwrap()).combos)
+    }
+
+    #[test]
+    fn dot_has_edges_failures_and_outputs() {
+        let a = automaton("[A], [B] -> \"Quoted\" Move\n[B] -> Tiny\n");
+        let dot = a.to_dot();
+        assert!(dot.starts_with("digraph automaton {"));
+        assert!(dot.contains("0 -> 1 [label=\"[A]\"];"));
+        assert!(dot.contains("1 -> 2 [label=\"[B]\"];"));
+        assert!(dot.contains("2 [label=\"2\\n\\\"Quoted\\\" Move\\nTiny\", shape=doublecircle];"));
+        assert!(dot.contains("2 -> 3 [style=dashed"));
+        assert!(dot.contains("1 -> 0 [style=dashed"));
+    }
+
+    #[test]
+    fn dot_can_hide_root_failures_and_highlight() {
+        let a = automaton("[A], [B] -> Long\n[B] -> Tiny\n");
+        let opts = DotOptions {
+            hide_root_failures: true,
+            highlight: vec!["[A]".to_string(), "[B]".to_string()],
+        };
+        let dot = a.to_dot_with(&opts);
+        assert!(!dot.contains("1 -> 0 [style=dashed"));
+        assert!(dot.contains("0 -> 1 [label=\"[A]\", color=red, penwidth=2];"));
+        assert!(dot.contains("1 -> 2 [label=\"[B]\", color=red, penwidth=2];"));
+        assert!(dot.contains("0 -> 3 [label=\"[B]\"];"));
+    }
+
+    #[test]
+    fn highlighted_failure_edges_have_one_color() {
+        let a = automaton("[A], [B] -> Long\n[B] -> Tiny\n");
+        let opts = DotOptions { hide_root_failures: false, highlight: ["[A]", "[B]", "[B]"].map(String::from).to_vec() };
+        let dot = a.to_dot_with(&opts);
+        let line = |prefix: &str| dot.lines().find(|l| l.trim_start().starts_with(prefix)).unwrap().trim().to_string();
+        assert_eq!(line("2 -> 3 [style"), "2 -> 3 [style=dashed, arrowhead=empty, color=red, penwidth=2];");
+        assert_eq!(line("1 -> 0 [style"), "1 -> 0 [style=dashed, arrowhead=empty, color=gray];");
+    }
+}
@@ -1,29 +1,263 @@
 use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 use std::time::Duration;
 
+use crate::attrs::{MoveInfo, RuleAttrs, Timing};
 use crate::automaton::Automaton;
-use crate::parse::{classify, parse_gmr_file};
+use crate::clock::Clock;
+use crate::dfa::{Dfa, MoveId, SymbolId};
+use crate::diag::{has_errors, render_all, Diagnostic};
+use crate::facing::{map_step, to_absolute, to_relative, Facing};
+use crate::input::{EventSource, InputEvent, KeyEventKind};
+use crate::pattern::{render_pattern, Pattern};
+use crate::policy::{ResolutionPolicy, TimeoutPolicy, UnknownKeyPolicy, NOISE};
+use crate::parse::{
+    chord_members, chord_token, classify, hold_token, parse_gmr_file_all, release_token, split_step,
+    CompiledGrammar, Setting, StepKind, Token,
+};
 
 pub const MAX_ALTS_PER_STEP: usize = 2;
+pub const DEFAULT_STEP_TIMEOUT: Duration = Duration::from_millis(500);
+pub const DEFAULT_CHORD_WINDOW: Duration = Duration::from_millis(50);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EngineOptions {
+    pub step_timeout: Duration,
+    /* key tokens arriving this close to the first one are merged into a chord */
+    pub chord_window: Duration,
+    /* refuse grammars with rules that `classify` had to discard */
+    pub strict: bool,
+    /* list combos with macros spelled out instead of as `$NAME` */
+    pub expand_macros: bool,
+    pub on_timeout: TimeoutPolicy,
+    pub on_unknown_key: UnknownKeyPolicy,
+    /* hold back moves whose combo may still grow into a longer one */
+    pub deferred: bool,
+    /* which of several moves completing at once fire */
+    pub resolution: ResolutionPolicy,
+    /* options given on the command line, which grammar `set` lines leave alone */
+    pub pinned: Pinned,
+}
+
+/* The options a grammar can also set, marked when they were given
+ * explicitly. */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Pinned {
+    pub step_timeout: bool,
+    pub on_timeout: bool,
+    pub on_unknown_key: bool,
+    pub resolution: bool,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        EngineOptions {
+            step_timeout: DEFAULT_STEP_TIMEOUT,
+            chord_window: DEFAULT_CHORD_WINDOW,
+            strict: false,
+            expand_macros: false,
+            on_timeout: TimeoutPolicy::default(),
+            on_unknown_key: UnknownKeyPolicy::default(),
+            deferred: false,
+            resolution: ResolutionPolicy::default(),
+            pinned: Pinned::default(),
+        }
+    }
+}
+
+/* The combos one character can perform, compiled on their own. `character`
+ * is None for a grammar without `[character "..."]` sections. */
 #[derive(Debug, Clone)]
-pub struct EngineConfig {
+pub struct Moveset {
+    pub character: Option<String>,
+    /* failure-link automaton, kept for inspection; stepping uses `dfa` */
     pub automaton: Automaton,
+    pub dfa: Dfa,
+    pub combos_internal: Vec<(Vec<String>, String)>,
+    /* per entry of `combos_internal`: its steps as symbol ids, and the
+     * trie state spelling them */
+    pub combo_syms: Vec<Vec<SymbolId>>,
+    pub combo_states: Vec<usize>,
+    /* attributes of each entry of `combos_internal` */
+    pub attrs: Vec<RuleAttrs>,
+    /* steps on each state's trie path */
+    pub depth: Vec<usize>,
+    /* entries of `combos_internal` per automaton output id */
+    pub combos_by_output: Vec<Vec<usize>>,
+    /* ms allowed before the next step, per state: the loosest `max_gap` of
+     * the combos that can continue from it, directly or from a suffix
+     * reached through failure links (the engine's step timeout if unset) */
+    pub state_timeout: Vec<u128>,
+    /* steps in the longest combo; how much input history is kept */
+    pub longest: usize,
+    pub combo_views: Vec<ComboView>,
+    /* members of every chord symbol, e.g. ["[BP]", "[FP]"], and its id */
+    pub chords: Vec<(Vec<String>, SymbolId)>,
+    /* what each bound key feeds, facing right and facing left */
+    pub keys: BTreeMap<String, (KeySymbols, KeySymbols)>,
+    /* fed for unbound keys under `on_unknown_key = noise` */
+    pub noise: SymbolId,
+}
+
+/* The symbols one bound key feeds the automaton from one side, looked up
+ * when the moveset is built. None for those no combo uses. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeySymbols {
+    /* relative internal token, e.g. "[Back]" */
+    pub internal: String,
+    pub press: Option<SymbolId>,
+    pub release: Option<SymbolId>,
+    /* (ms, symbol) for each hold of it the grammar asks for, ascending */
+    pub holds: Vec<(u64, SymbolId)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    /* one per character, in declaration order; never empty */
+    pub movesets: Vec<Moveset>,
     pub key_to_internal: BTreeMap<String, String>,
     pub internal_to_keys: BTreeMap<String, BTreeSet<String>>,
     pub bindings_display: Vec<(String, String)>,
-    pub combos_internal: Vec<(Vec<String>, String)>,
     pub step_timeout: Duration,
+    pub chord_window: Duration,
+    pub expand_macros: bool,
+    pub on_timeout: TimeoutPolicy,
+    pub on_unknown_key: UnknownKeyPolicy,
+    pub deferred: bool,
+    pub resolution: ResolutionPolicy,
+    /* non-fatal grammar problems found while loading */
+    pub warnings: Vec<Diagnostic>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/* One combo line as written, e.g. `[Left]*2, ([FP] | [BP])`, with the
+ * internal sequences it expanded into. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComboView {
+    pub pattern: Vec<Pattern>,
+    pub move_name: String,
+    pub info: MoveInfo,
+    pub variants: Vec<Vec<String>>,
+}
+
+/* Presses that may still grow into a chord: `base_state` is where the
+ * automaton stood before the first of them. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChordGroup {
+    pub base_state: usize,
+    pub members: Vec<String>,
+    pub start_ms: u128,
+    /* length of `EngineState::history` before the first of them */
+    pub history_len: usize,
+    /* what the group completed so far, kept until a longer chord can no
+     * longer replace it */
+    pub held_back: StepOutcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct EngineState {
     pub cur_state: usize,
     pub last_time_ms: Option<u128>,
+    pub chord: Option<ChordGroup>,
+    /* key token -> press time, for keys currently down */
+    pub held: BTreeMap<String, u128>,
+    /* index into `EngineConfig::movesets` */
+    pub character: usize,
+    /* left/right keys are read as forward/back from this side */
+    pub facing: Facing,
+    /* recent (symbol, arrived, completed) times, up to the longest combo;
+     * they differ for holds and chords. Timing limits are checked against
+     * it when a combo completes. */
+    pub history: Vec<(SymbolId, u128, u128)>,
+    /* deferred mode: moves completed at a state the combo may still grow
+     * from, waiting for the next step or the timeout, each with the depth
+     * of the trie path its combo starts at */
+    pub pending: Vec<(MoveHit, usize)>,
+}
+
+/* A timing limit a completed combo broke. Steps are numbered from 1. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Constraint {
+    MaxGap { step: usize, gap: u128, limit: u128 },
+    MinGap { step: usize, gap: u128, limit: u128 },
+    MaxTotal { total: u128, limit: u128 },
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constraint::MaxGap { step, gap, limit } =>
+                write!(f, "step {step} came {gap}ms after the previous one (max_gap={limit})"),
+            Constraint::MinGap { step, gap, limit } =>
+                write!(f, "step {step} came {gap}ms after the previous one (min_gap={limit})"),
+            Constraint::MaxTotal { total, limit } => write!(f, "took {total}ms in total (max_total={limit})"),
+        }
+    }
+}
+
+/* A combo whose steps all arrived, but out of time. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub move_name: String,
+    pub constraint: Constraint,
 }
 
-fn format_engine_info(cfg: &EngineConfig) -> String {
+/* Whether a move's combo spells the whole path to the current state, or
+ * only a suffix of it (reached through failure links). */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MatchSource {
+    Direct,
+    Suffix,
+}
+
+/* A move that fired, with its rule's metadata. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MoveHit {
+    pub name: String,
+    pub source: MatchSource,
+    pub info: MoveInfo,
+    /* steps in the combo that matched */
+    pub steps: usize,
+}
+
+/* What became of a move held back in deferred mode. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoveEvent {
+    /* its combo could not grow any further; it fires now */
+    Confirmed(String),
+    /* a longer combo through it completed instead */
+    Cancelled(String),
+}
+
+/* What one input did: moves fired (ordered by the resolution policy),
+ * combos refused for their timing and, in deferred mode, held-back moves
+ * resolved. Confirmed moves are also listed in `moves`. */
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StepOutcome {
+    pub moves: Vec<MoveHit>,
+    pub violations: Vec<Violation>,
+    pub events: Vec<MoveEvent>,
+}
+
+impl StepOutcome {
+    pub fn move_names(&self) -> Vec<String> {
+        self.moves.iter().map(|m| m.name.clone()).collect()
+    }
+
+    fn extend(mut self, other: StepOutcome) -> StepOutcome {
+        self.moves.extend(other.moves);
+        self.violations.extend(other.violations);
+        self.events.extend(other.events);
+        self
+    }
+}
+
+pub fn format_engine_info(cfg: &EngineConfig, st: &EngineState) -> String {
     let mut output = String::new();
+    let ms = moveset(cfg, st);
+
+    if let Some(name) = &ms.character {
+        output.push_str(&format!("Character: {name} ({}/{})\n", st.character + 1, cfg.movesets.len()));
+    }
     
     output.push_str("Key mappings:\n");
     for (key, internal) in &cfg.bindings_display {
@@ -32,34 +266,204 @@ fn format_engine_info(cfg: &EngineConfig) -> String {
     
     output.push_str("----------------------\n");
     
-    let mut grouped_combos: BTreeMap<Vec<String>, Vec<String>> = BTreeMap::new();
-    for (steps, move_name) in &cfg.combos_internal {
-        grouped_combos.entry(steps.clone()).or_default().push(move_name.clone());
+    let mut grouped_combos: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for view in &ms.combo_views {
+        let written = render_pattern(&view.pattern, cfg.expand_macros, &|t| t.to_string());
+        grouped_combos.entry(written).or_default().push(move_label(&view.move_name, &view.info, ms.character.as_deref()));
     }
     
-    for (steps, moves) in grouped_combos {
-        output.push_str(&format!("{}\n", steps.join(", ")));
+    for (written, moves) in grouped_combos {
+        output.push_str(&format!("{}\n", written));
         for move_name in moves {
             output.push_str(&format!("{} !!\n", move_name));
         }
-        output.push_str("\n");
+        output.push('\n');
     }
     
     output
 }
 
-pub fn print_engine(cfg: &EngineConfig) {
-    print!("{}", format_engine_info(cfg));
+/* "Fireball (Generic, 12 dmg)", or just the name without metadata. A
+ * character already named by the moveset is left out. */
+pub fn move_label(name: &str, info: &MoveInfo, character: Option<&str>) -> String {
+    let info = match &info.character {
+        Some(c) if Some(c.as_str()) == character => MoveInfo { character: None, ..info.clone() },
+        _ => info.clone(),
+    };
+    if info.is_empty() { name.to_string() } else { format!("{name} ({info})") }
+}
+
+pub fn print_engine(cfg: &EngineConfig, st: &EngineState) {
+    print!("{}", format_engine_info(cfg, st));
+}
+
+/* Combos are written facing right; the automaton only sees relative
+ * directions, so "[Right]" and "[Fwd]" are the same step. */
+fn build_moveset(
+    character: Option<String>,
+    combos: &[crate::parse::Rule],
+    key_to_internal: &BTreeMap<String, String>,
+    step_timeout: u128,
+) -> Moveset {
+    let relative = |t: &Token| Token::new(map_step(t.as_str(), &|m| to_relative(m, Facing::Right)));
+    let combos: Vec<crate::parse::Rule> = combos
+        .iter()
+        .map(|r| crate::parse::Rule { sequence: r.sequence.iter().map(relative).collect(), ..r.clone() })
+        .collect();
+    let combos = combos.as_slice();
+    let automaton = Automaton::from_combos(combos);
+    let dfa = automaton.compile();
+
+    let combos_internal: Vec<(Vec<String>, String)> = combos
+        .iter()
+        .map(|r| {
+            let steps: Vec<String> = r.sequence.iter().map(|t| t.as_str().to_string()).collect();
+            (steps, r.move_name.clone())
+        })
+        .collect();
+    let attrs: Vec<RuleAttrs> = combos.iter().map(|r| r.attrs.clone()).collect();
+    let symbol = |tok: &str| dfa.symbol(tok).expect("combo steps are automaton symbols");
+    let combo_syms: Vec<Vec<SymbolId>> =
+        combos_internal.iter().map(|(steps, _)| steps.iter().map(|t| symbol(t)).collect()).collect();
+    let combo_states: Vec<usize> = combos_internal
+        .iter()
+        .map(|(steps, _)| automaton.trie_state(steps).expect("combos spell trie paths"))
+        .collect();
+
+    let mut loosest: Vec<Option<u128>> = vec![None; dfa.num_states()];
+    for (r, syms) in combos.iter().zip(&combo_syms) {
+        let limit = r.attrs.timing.max_gap.map_or(step_timeout, u128::from);
+        let (_, prefix) = syms.split_last().expect("combos are never empty");
+        prefix.iter().fold(0, |state, &sym| {
+            let next = dfa.next(state, sym);
+            loosest[next] = Some(loosest[next].map_or(limit, |l| l.max(limit)));
+            next
+        });
+    }
+    /* a step the state has no edge for can still continue a combo from
+     * one of its suffixes; parents come before children in `order` */
+    let mut order = vec![0];
+    let mut depth = vec![0; dfa.num_states()];
+    let mut i = 0;
+    while i < order.len() {
+        let from = order[i];
+        for (_, to) in automaton.goto_edges(from) {
+            depth[to] = depth[from] + 1;
+            order.push(to);
+        }
+        i += 1;
+    }
+    for &state in &order[1..] {
+        loosest[state] = loosest[state].max(loosest[automaton.fail(state)]);
+    }
+    let state_timeout: Vec<u128> = loosest.into_iter().map(|l| l.unwrap_or(step_timeout)).collect();
+    let output_ids: BTreeMap<&str, MoveId> = (0..dfa.num_outputs()).map(|id| (dfa.output_name(id), id)).collect();
+    let mut combos_by_output: Vec<Vec<usize>> = vec![Vec::new(); dfa.num_outputs()];
+    for (i, (_, name)) in combos_internal.iter().enumerate() {
+        if let Some(&id) = output_ids.get(name.as_str()) {
+            combos_by_output[id].push(i);
+        }
+    }
+    let longest = combos.iter().map(|r| r.sequence.len()).max().unwrap_or(0);
+
+    /* rules expanded from one line are adjacent and share loc and name */
+    let mut combo_views: Vec<ComboView> = Vec::new();
+    for (i, r) in combos.iter().enumerate() {
+        let steps: Vec<String> = r.sequence.iter().map(|t| t.as_str().to_string()).collect();
+        let same_line = i > 0 && combos[i - 1].loc == r.loc && combos[i - 1].move_name == r.move_name;
+        match combo_views.last_mut() {
+            Some(last) if same_line => last.variants.push(steps),
+            _ => combo_views.push(ComboView {
+                pattern: r.pattern.clone(),
+                move_name: r.move_name.clone(),
+                info: r.attrs.info.clone(),
+                variants: vec![steps],
+            }),
+        }
+    }
+
+    let chords = automaton
+        .chord_symbols()
+        .into_iter()
+        .map(|members| {
+            let members: Vec<String> = members.into_iter().map(str::to_string).collect();
+            let sym = symbol(&chord_token(&members));
+            (members, sym)
+        })
+        .collect();
+
+    let key_symbols = |internal: &str, facing: Facing| {
+        let internal = to_relative(internal, facing);
+        KeySymbols {
+            press: dfa.symbol(&internal),
+            release: dfa.symbol(&release_token(&internal)),
+            holds: automaton
+                .hold_thresholds(&internal)
+                .iter()
+                .map(|&ms| (ms, symbol(&hold_token(&internal, ms))))
+                .collect(),
+            internal,
+        }
+    };
+    let keys = key_to_internal
+        .iter()
+        .map(|(key, i)| (key.clone(), (key_symbols(i, Facing::Right), key_symbols(i, Facing::Left))))
+        .collect();
+    let noise = dfa.symbol(NOISE).unwrap_or(dfa.unknown());
+
+    Moveset {
+        character,
+        automaton,
+        dfa,
+        combos_internal,
+        combo_syms,
+        combo_states,
+        attrs,
+        depth,
+        combos_by_output,
+        state_timeout,
+        longest,
+        combo_views,
+        chords,
+        keys,
+        noise,
+    }
+}
+
+/* One moveset per character; combos outside any section go into each. */
+fn build_movesets(
+    combos: &[crate::parse::Rule],
+    key_to_internal: &BTreeMap<String, String>,
+    step_timeout: u128,
+) -> Vec<Moveset> {
+    let characters: Vec<&str> = combos.iter().filter_map(|r| r.character.as_deref()).fold(Vec::new(), |mut acc, c| {
+        if !acc.contains(&c) {
+            acc.push(c);
+        }
+        acc
+    });
+    if characters.is_empty() {
+        return vec![build_moveset(None, combos, key_to_internal, step_timeout)];
+    }
+    characters
+        .into_iter()
+        .map(|c| {
+            let own: Vec<crate::parse::Rule> = combos
+                .iter()
+                .filter(|r| r.character.as_deref().is_none_or(|rc| rc == c))
+                .cloned()
+                .collect();
+            build_moveset(Some(c.to_string()), &own, key_to_internal, step_timeout)
+        })
+        .collect()
 }
 
 pub fn build_engine(
     combos: &[crate::parse::Rule],
     bindings: &[(String, String)],
-    step_timeout: Duration,
+    opts: &EngineOptions,
 ) -> (EngineConfig, EngineState) {
-    let automaton = Automaton::from_combos(combos);
-
-    let mut bindings_display: Vec<(String, String)> = bindings.iter().cloned().collect();
+    let mut bindings_display: Vec<(String, String)> = bindings.to_vec();
     bindings_display.sort_by(|a, b| a.0.cmp(&b.0));
 
     /* key -> internal */
@@ -75,94 +479,1180 @@ pub fn build_engine(
         m
     };
 
-    /* combos */
-    let combos_internal: Vec<(Vec<String>, String)> = combos
-        .iter()
-        .map(|r| {
-            let steps: Vec<String> = r.sequence.iter().map(|t| t.as_str().to_string()).collect();
-            (steps, r.move_name.clone())
-        })
-        .collect();
-
     let cfg = EngineConfig {
-        automaton,
+        movesets: build_movesets(combos, &key_to_internal, opts.step_timeout.as_millis()),
         key_to_internal,
         internal_to_keys,
         bindings_display,
-        combos_internal,
-        step_timeout,
+        step_timeout: opts.step_timeout,
+        chord_window: opts.chord_window,
+        expand_macros: opts.expand_macros,
+        on_timeout: opts.on_timeout,
+        on_unknown_key: opts.on_unknown_key,
+        deferred: opts.deferred,
+        resolution: opts.resolution,
+        warnings: Vec::new(),
+    };
+    let st = EngineState {
+        cur_state: 0,
+        last_time_ms: None,
+        chord: None,
+        held: BTreeMap::new(),
+        character: 0,
+        facing: Facing::default(),
+        history: Vec::new(),
+        pending: Vec::new(),
     };
-    let st = EngineState { cur_state: 0, last_time_ms: None };
 
     (cfg, st)
 }
 
+/* The active character's moveset. */
+pub fn moveset<'a>(cfg: &'a EngineConfig, st: &EngineState) -> &'a Moveset {
+    &cfg.movesets[st.character.min(cfg.movesets.len() - 1)]
+}
+
+pub fn character_names(cfg: &EngineConfig) -> Vec<&str> {
+    cfg.movesets.iter().filter_map(|m| m.character.as_deref()).collect()
+}
+
+/* Switches to the character called `name`, dropping any combo in progress. */
+pub fn select_character(cfg: &EngineConfig, st: EngineState, name: &str) -> Option<EngineState> {
+    let idx = cfg.movesets.iter().position(|m| m.character.as_deref() == Some(name))?;
+    Some(EngineState { character: idx, ..reset(cfg, st) })
+}
+
+/* Switches to the next character, wrapping around. */
+pub fn next_character(cfg: &EngineConfig, st: EngineState) -> EngineState {
+    let idx = (st.character + 1) % cfg.movesets.len();
+    EngineState { character: idx, ..reset(cfg, st) }
+}
+
 pub fn bindings(cfg: &EngineConfig) -> &[(String, String)] { &cfg.bindings_display }
-pub fn combos_internal(cfg: &EngineConfig) -> &[(Vec<String>, String)] { &cfg.combos_internal }
-
-pub fn display_for_internal(cfg: &EngineConfig, internal: &str) -> String {
-    match cfg.internal_to_keys.get(internal) {
-        Some(list) if !list.is_empty() => {
-            let shown: Vec<String> = list.iter().take(MAX_ALTS_PER_STEP).cloned().collect();
-            if list.len() > MAX_ALTS_PER_STEP {
-                format!("{} / …", shown.join(" / "))
-            } else {
-                shown.join(" / ")
+pub fn combos_internal<'a>(cfg: &'a EngineConfig, st: &EngineState) -> &'a [(Vec<String>, String)] {
+    &moveset(cfg, st).combos_internal
+}
+pub fn combo_views<'a>(cfg: &'a EngineConfig, st: &EngineState) -> &'a [ComboView] { &moveset(cfg, st).combo_views }
+
+/* A combo as its author wrote it, steps shown with their bound keys. */
+pub fn display_for_combo(cfg: &EngineConfig, st: &EngineState, view: &ComboView) -> String {
+    display_for_combo_with(cfg, st, view, &|k| k.to_string())
+}
+
+/* Same, with each key name passed through `key_label` (e.g. "left" -> "←"). */
+pub fn display_for_combo_with(
+    cfg: &EngineConfig,
+    st: &EngineState,
+    view: &ComboView,
+    key_label: &dyn Fn(&str) -> String,
+) -> String {
+    render_pattern(&view.pattern, cfg.expand_macros, &|t| step_label(cfg, st, t, key_label))
+}
+
+/* Keys for a step as the player must press them from their current side. */
+pub fn display_for_internal(cfg: &EngineConfig, st: &EngineState, internal: &str) -> String {
+    step_label(cfg, st, internal, &|k| k.to_string())
+}
+
+fn step_label(cfg: &EngineConfig, st: &EngineState, internal: &str, key_label: &dyn Fn(&str) -> String) -> String {
+    let members = chord_members(internal);
+    if members.len() > 1 {
+        return members
+            .iter()
+            .map(|m| step_label(cfg, st, m, key_label))
+            .collect::<Vec<_>>()
+            .join(" + ");
+    }
+    match split_step(internal) {
+        (StepKind::Release, base) => return format!("^{}", step_label(cfg, st, base, key_label)),
+        (StepKind::Hold(ms), base) => return format!("{} ~{ms}ms", step_label(cfg, st, base, key_label)),
+        (StepKind::Press, _) => {}
+    }
+    /* keys for the absolute direction first, then those bound to the
+     * relative one; a step with no direction looks up the same list twice */
+    let relative = to_relative(internal, Facing::Right);
+    let absolute = to_absolute(&relative, st.facing);
+    let list: Vec<&String> = [absolute.as_str(), relative.as_str()]
+        .iter()
+        .filter_map(|t| cfg.internal_to_keys.get(*t))
+        .flatten()
+        .fold(Vec::new(), |mut acc, k| {
+            if !acc.contains(&k) {
+                acc.push(k);
             }
-        }
-        _ => internal.to_string(),
+            acc
+        });
+    if list.is_empty() {
+        return internal.to_string();
+    }
+    let shown: Vec<String> = list.iter().take(MAX_ALTS_PER_STEP).map(|k| key_label(k)).collect();
+    if list.len() > MAX_ALTS_PER_STEP {
+        format!("{} / …", shown.join(" / "))
+    } else {
+        shown.join(" / ")
     }
 }
 
-pub fn matched_prefix_len(cfg: &EngineConfig, target_state: usize, steps: &[String]) -> usize {
+/* How many leading steps of `steps` the engine's current state stands for. */
+pub fn matched_prefix_len(cfg: &EngineConfig, engine: &EngineState, steps: &[String]) -> usize {
+    let dfa = &moveset(cfg, engine).dfa;
     let mut st = 0usize;
     for (i, tok) in steps.iter().enumerate() {
-        let (nxt, _) = cfg.automaton.step(st, tok);
-        st = nxt;
-        if st == target_state { return i + 1; }
+        st = dfa.next(st, dfa.symbol(tok).unwrap_or(dfa.unknown()));
+        if st == engine.cur_state { return i + 1; }
     }
     0
 }
 
+/* The chord `internal` would extend the open group into, if it arrived in
+ * time and some chord symbol still contains all pressed members. */
+fn chord_candidate(cfg: &EngineConfig, st: &EngineState, internal: &str, now_ms: u128)
+    -> Option<Vec<String>>
+{
+    let group = st.chord.as_ref()?;
+    if now_ms.saturating_sub(group.start_ms) > cfg.chord_window.as_millis()
+        || group.members.iter().any(|m| m == internal)
+    {
+        return None;
+    }
+    let mut members = group.members.clone();
+    members.push(internal.to_string());
+    moveset(cfg, st)
+        .chords
+        .iter()
+        .any(|(chord, _)| members.iter().all(|m| chord.contains(m)))
+        .then_some(members)
+}
+
+/* The chord symbol made of exactly `members`, if the grammar has one. */
+fn chord_symbol(ms: &Moveset, members: &[String]) -> Option<SymbolId> {
+    ms.chords
+        .iter()
+        .find(|(chord, _)| chord.len() == members.len() && members.iter().all(|m| chord.contains(m)))
+        .map(|&(_, sym)| sym)
+}
+
+/* Whether some chord symbol has every one of `members` and more, so that
+ * another press inside the window could still turn them into it. */
+fn chord_may_grow(ms: &Moveset, members: &[String]) -> bool {
+    ms.chords.iter().any(|(chord, _)| chord.len() > members.len() && members.iter().all(|m| chord.contains(m)))
+}
+
+/* Ends the open chord group: what it held back fires (and, in deferred
+ * mode, is held back again if its combo may still grow). */
+fn close_chord(cfg: &EngineConfig, st: EngineState) -> (EngineState, StepOutcome) {
+    let Some(group) = st.chord.clone() else {
+        return (st, StepOutcome::default());
+    };
+    let st = EngineState { chord: None, ..st };
+    if cfg.deferred {
+        defer(cfg, (group.base_state, None), st, group.held_back)
+    } else {
+        (st, group.held_back)
+    }
+}
+
+/* The first limit `timing` breaks for the history entries `steps`. A gap
+ * runs from one step's completion to the next one's arrival, as for the
+ * step timeout, which it falls back to without a `max_gap` (unless the
+ * timeout policy forgives late steps). */
+fn check_timing(timing: &Timing, step_timeout: Option<u128>, steps: &[(SymbolId, u128, u128)]) -> Result<(), Constraint> {
+    for (i, pair) in steps.windows(2).enumerate() {
+        let (step, gap) = (i + 2, pair[1].1.saturating_sub(pair[0].2));
+        if let Some(limit) = timing.max_gap.map(u128::from).or(step_timeout).filter(|&l| gap > l) {
+            return Err(Constraint::MaxGap { step, gap, limit });
+        }
+        if let Some(limit) = timing.min_gap.map(u128::from).filter(|&l| gap < l) {
+            return Err(Constraint::MinGap { step, gap, limit });
+        }
+    }
+    let total = steps.last().zip(steps.first()).map_or(0, |(last, first)| last.2.saturating_sub(first.1));
+    match timing.max_total.map(u128::from) {
+        Some(limit) if total > limit => Err(Constraint::MaxTotal { total, limit }),
+        _ => Ok(()),
+    }
+}
+
+/* A move completed by this step, with what resolution ranks it by. */
+struct Candidate {
+    hit: MoveHit,
+    /* position of its combo in `combos_internal` */
+    index: usize,
+    len: usize,
+    priority: i32,
+}
+
+/* Splits the automaton's outputs into moves and violations: a move fires
+ * if any combo of that name ending the history kept to its timing (the
+ * longest such combo stands for it). */
+fn check_outputs(cfg: &EngineConfig, st: &EngineState, outs: &[MoveId]) -> StepOutcome {
+    let ms = moveset(cfg, st);
+    let step_timeout = (cfg.on_timeout == TimeoutPolicy::Reset).then_some(cfg.step_timeout.as_millis());
+    let mut violations = Vec::new();
+    let mut candidates = Vec::new();
+    for &id in outs {
+        let name = ms.dfa.output_name(id);
+        /* the longest (then first) combo that kept time, else the first
+         * limit broken */
+        let mut passed: Option<usize> = None;
+        let mut broken: Option<Constraint> = None;
+        for &i in &ms.combos_by_output[id] {
+            let steps = &ms.combo_syms[i];
+            let Some(tail) = st.history.len().checked_sub(steps.len()).map(|from| &st.history[from..]) else {
+                continue;
+            };
+            if !tail.iter().zip(steps).all(|((sym, _, _), step)| sym == step) {
+                continue;
+            }
+            match check_timing(&ms.attrs[i].timing, step_timeout, tail) {
+                Ok(()) if passed.is_none_or(|p| steps.len() > ms.combo_syms[p].len()) => passed = Some(i),
+                Ok(()) => {}
+                Err(constraint) => {
+                    broken.get_or_insert(constraint);
+                }
+            }
+        }
+        let candidate = |i: usize| {
+            let source = if ms.combo_states[i] == st.cur_state { MatchSource::Direct } else { MatchSource::Suffix };
+            let steps = ms.combo_syms[i].len();
+            let hit = MoveHit { name: name.to_string(), source, info: ms.attrs[i].info.clone(), steps };
+            Candidate { hit, index: i, len: steps, priority: ms.attrs[i].priority }
+        };
+        match (passed, broken) {
+            (None, Some(constraint)) => {
+                violations.push(Violation { move_name: name.to_string(), constraint })
+            }
+            (Some(i), _) => candidates.push(candidate(i)),
+            /* no combo of it ends the kept history, so there is nothing to
+             * time: the first one declared stands for it */
+            (None, None) => candidates.push(candidate(ms.combos_by_output[id][0])),
+        }
+    }
+    StepOutcome { moves: resolve(cfg.resolution, candidates), violations, events: Vec::new() }
+}
+
+/* Winners under `policy`, in declaration order; ties all fire. */
+fn resolve(policy: ResolutionPolicy, mut candidates: Vec<Candidate>) -> Vec<MoveHit> {
+    candidates.sort_by_key(|c| c.index);
+    let best = |key: fn(&Candidate) -> i64, candidates: Vec<Candidate>| {
+        let top = candidates.iter().map(key).max();
+        candidates.into_iter().filter(|c| Some(key(c)) == top).collect()
+    };
+    let winners: Vec<Candidate> = match policy {
+        ResolutionPolicy::All => candidates,
+        ResolutionPolicy::HighestPriority => best(|c| i64::from(c.priority), candidates),
+        ResolutionPolicy::LongestMatch => best(|c| c.len as i64, candidates),
+        ResolutionPolicy::FirstDeclared => candidates.into_iter().take(1).collect(),
+    };
+    winners.into_iter().map(|c| c.hit).collect()
+}
+
+/* `history` with `symbol` appended, trimmed to the longest combo. */
+fn push_history(ms: &Moveset, mut history: Vec<(SymbolId, u128, u128)>, symbol: SymbolId, arrived_ms: u128, now_ms: u128)
+    -> Vec<(SymbolId, u128, u128)>
+{
+    history.push((symbol, arrived_ms, now_ms));
+    let excess = history.len().saturating_sub(ms.longest);
+    history.drain(..excess);
+    history
+}
+
+/* Whether a step arriving at `arrived_ms` comes after the timeout of the
+ * state the combo has reached. */
+pub fn is_late(cfg: &EngineConfig, st: &EngineState, arrived_ms: u128) -> bool {
+    let limit = moveset(cfg, st).state_timeout.get(st.cur_state).copied().unwrap_or(cfg.step_timeout.as_millis());
+    st.last_time_ms.is_some_and(|prev| arrived_ms.saturating_sub(prev) > limit)
+}
+
+/* Feeds one automaton symbol. The timeout is measured from `arrived_ms`
+ * (the press time, for holds) to the previous step, and depends on the
+ * state the combo has reached; `cfg.on_timeout` decides what a late step
+ * continues from. */
+fn advance(cfg: &EngineConfig, st: EngineState, symbol: SymbolId, arrived_ms: u128, now_ms: u128)
+    -> (EngineState, usize, StepOutcome)
+{
+    let ms = moveset(cfg, &st);
+    let late = is_late(cfg, &st, arrived_ms);
+    let (base_state, history) = match cfg.on_timeout {
+        TimeoutPolicy::Reset if late => (0, Vec::new()),
+        TimeoutPolicy::FailureLink if late => (ms.automaton.fail(st.cur_state), st.history.clone()),
+        _ => (st.cur_state, st.history.clone()),
+    };
+    let history = push_history(ms, history, symbol, arrived_ms, now_ms);
+    let (next, outs) = ms.dfa.step(base_state, symbol);
+    let missed_from = (base_state != st.cur_state).then(|| st.clone());
+    let st = EngineState { cur_state: next, last_time_ms: Some(now_ms), history, ..st };
+    let outcome = check_outputs(cfg, &st, outs);
+    let violations = match missed_from {
+        Some(before) => missed(cfg, &before, symbol, arrived_ms, now_ms, &outcome),
+        None => Vec::new(),
+    };
+    let outcome = outcome.extend(StepOutcome { violations, ..StepOutcome::default() });
+    (st, base_state, outcome)
+}
+
+/* The combos a late step would have completed from the state it left:
+ * each broke a limit, so they are reported rather than dropped. Moves
+ * `fired` anyway are not. */
+fn missed(cfg: &EngineConfig, st: &EngineState, symbol: SymbolId, arrived_ms: u128, now_ms: u128, fired: &StepOutcome)
+    -> Vec<Violation>
+{
+    let ms = moveset(cfg, st);
+    let (next, outs) = ms.dfa.step(st.cur_state, symbol);
+    let history = push_history(ms, st.history.clone(), symbol, arrived_ms, now_ms);
+    let would = EngineState { cur_state: next, history, ..st.clone() };
+    check_outputs(cfg, &would, outs)
+        .violations
+        .into_iter()
+        .filter(|v| !fired.moves.iter().any(|m| m.name == v.move_name) && !fired.violations.contains(v))
+        .collect()
+}
+
+/* Keys whose plain press never appears in a combo, but whose hold or
+ * release does, must not disturb the state when pressed. */
+fn press_is_transparent(keysyms: &KeySymbols) -> bool {
+    keysyms.press.is_none() && (keysyms.release.is_some() || !keysyms.holds.is_empty())
+}
+
+/* The symbols `key` feeds from `facing`; None if it is not bound. */
+fn key_symbols<'a>(ms: &'a Moveset, key: &str, facing: Facing) -> Option<&'a KeySymbols> {
+    ms.keys.get(key).map(|(right, left)| match facing {
+        Facing::Right => right,
+        Facing::Left => left,
+    })
+}
+
+/* Steps on a key-down. The second outcome is what an earlier chord group
+ * held back, released because this press closed it. */
+fn step_press(cfg: &EngineConfig, st: EngineState, key: &str, now_ms: u128)
+    -> (EngineState, StepOutcome, StepOutcome)
+{
+    let ms = moveset(cfg, &st);
+    let mut held = st.held.clone();
+    held.insert(key.to_string(), now_ms);
+
+    let keysyms = match (key_symbols(ms, key, st.facing), cfg.on_unknown_key) {
+        (Some(k), _) => k,
+        (None, UnknownKeyPolicy::Reset) => {
+            let (st, released) = close_chord(cfg, st);
+            let st = EngineState { cur_state: 0, last_time_ms: Some(now_ms), held, history: Vec::new(), ..st };
+            return (st, released, StepOutcome::default());
+        }
+        (None, UnknownKeyPolicy::Ignore) => {
+            return (EngineState { held, ..st }, StepOutcome::default(), StepOutcome::default());
+        }
+        (None, UnknownKeyPolicy::Noise) => {
+            let (st, released) = close_chord(cfg, st);
+            let (st, _, outcome) = advance(cfg, EngineState { held, ..st }, ms.noise, now_ms, now_ms);
+            return (st, released, outcome);
+        }
+    };
+    let internal = keysyms.internal.as_str();
+
+    if press_is_transparent(keysyms) {
+        return (EngineState { held, ..st }, StepOutcome::default(), StepOutcome::default());
+    }
+
+    if let Some(members) = chord_candidate(cfg, &st, internal, now_ms) {
+        /* Re-run from before the group with the chord symbol, dropping what
+         * its first presses held back; a partial chord (e.g. 2 of 3
+         * buttons) waits for the rest. */
+        let group = st.chord.clone().expect("chord candidate implies an open group");
+        let Some(chord) = chord_symbol(ms, &members) else {
+            let chord_group = ChordGroup { members, ..group };
+            let st = EngineState { last_time_ms: Some(now_ms), chord: Some(chord_group), held, ..st };
+            return (st, StepOutcome::default(), StepOutcome::default());
+        };
+        let mut history = st.history.clone();
+        history.truncate(group.history_len);
+        let history = push_history(ms, history, chord, group.start_ms, now_ms);
+        let (next, outs) = ms.dfa.step(group.base_state, chord);
+        let st = EngineState { cur_state: next, last_time_ms: Some(now_ms), held, history, ..st };
+        let outcome = check_outputs(cfg, &st, outs);
+        let (outcome, held_back) = match chord_may_grow(ms, &members) {
+            true => (StepOutcome::default(), outcome),
+            false => (outcome, StepOutcome::default()),
+        };
+        let chord_group = ChordGroup { members, held_back, ..group };
+        return (EngineState { chord: Some(chord_group), ..st }, StepOutcome::default(), outcome);
+    }
+
+    let (st, released) = close_chord(cfg, st);
+    let press = keysyms.press.unwrap_or(ms.dfa.unknown());
+    let (st, base_state, outcome) = advance(cfg, EngineState { held, ..st }, press, now_ms, now_ms);
+    let members = vec![internal.to_string()];
+    let (outcome, held_back) = match chord_may_grow(ms, &members) {
+        true => (StepOutcome::default(), outcome),
+        false => (outcome, StepOutcome::default()),
+    };
+    let chord = ChordGroup {
+        base_state,
+        members,
+        start_ms: now_ms,
+        history_len: st.history.len().saturating_sub(1),
+        held_back,
+    };
+    (EngineState { chord: Some(chord), ..st }, released, outcome)
+}
+
+/* A release feeds the longest satisfied hold ("[Back]~800") and then the
+ * release itself ("^[Back]"), each only if the grammar uses it; feeding
+ * either closes the chord group, as for `step_press`. */
+fn step_release(cfg: &EngineConfig, st: EngineState, key: &str, now_ms: u128)
+    -> (EngineState, StepOutcome, StepOutcome)
+{
+    let ms = moveset(cfg, &st);
+    let mut held = st.held.clone();
+    let pressed_at = held.remove(key);
+    let st = EngineState { held, ..st };
+
+    let Some(keysyms) = key_symbols(ms, key, st.facing) else {
+        return (st, StepOutcome::default(), StepOutcome::default());
+    };
+
+    let hold = pressed_at.and_then(|t0| {
+        let held_ms = now_ms.saturating_sub(t0);
+        keysyms.holds.iter().rev().find(|&&(ms, _)| u128::from(ms) <= held_ms).map(|&(_, sym)| (sym, t0))
+    });
+    let release = keysyms.release.map(|sym| (sym, now_ms));
+    let symbols: Vec<(SymbolId, u128)> = hold.into_iter().chain(release).collect();
+    if symbols.is_empty() {
+        return (st, StepOutcome::default(), StepOutcome::default());
+    }
+
+    let (st, released) = close_chord(cfg, st);
+    let (st, outcome) = symbols.into_iter().fold((st, StepOutcome::default()), |(st, outcome), (symbol, arrived)| {
+        let (st2, _, more) = advance(cfg, st, symbol, arrived, now_ms);
+        (st2, outcome.extend(more))
+    });
+    (st, released, outcome)
+}
+
+/* Fires the held-back moves. */
+fn confirm(st: EngineState) -> (EngineState, StepOutcome) {
+    let outcome = StepOutcome {
+        events: st.pending.iter().map(|(m, _)| MoveEvent::Confirmed(m.name.clone())).collect(),
+        moves: st.pending.iter().map(|(m, _)| m.clone()).collect(),
+        violations: Vec::new(),
+    };
+    (EngineState { pending: Vec::new(), ..st }, outcome)
+}
+
+/* Deferred mode: held-back moves fire once the step timeout since the
+ * last step has run out at `now_ms`. */
+fn settle(cfg: &EngineConfig, st: EngineState, now_ms: u128) -> (EngineState, StepOutcome) {
+    let limit = moveset(cfg, &st).state_timeout.get(st.cur_state).copied().unwrap_or(cfg.step_timeout.as_millis());
+    match st.last_time_ms {
+        Some(prev) if !st.pending.is_empty() && now_ms.saturating_sub(prev) > limit => confirm(st),
+        _ => (st, StepOutcome::default()),
+    }
+}
+
+/* Deferred mode, after a step from `before`: a step along a trie edge keeps
+ * the held-back moves waiting, and cancels those a combo it completed
+ * reaches back over; any other step confirms them. New moves are then held
+ * back if their state has edges out, and fire otherwise (with whatever is
+ * still held). */
+fn defer(cfg: &EngineConfig, before: (usize, Option<u128>), st: EngineState, outcome: StepOutcome)
+    -> (EngineState, StepOutcome)
+{
+    if before == (st.cur_state, st.last_time_ms) {
+        /* nothing was fed (ignored or transparent key) */
+        return (st, outcome);
+    }
+    let ms = moveset(cfg, &st);
+    let automaton = &ms.automaton;
+    let depth = ms.depth[st.cur_state];
+    let grew = automaton.goto_edges(before.0).any(|(_, to)| to == st.cur_state);
+    let (st, resolved) = match grew {
+        true => {
+            let reach = outcome.moves.iter().map(|m| depth.saturating_sub(m.steps)).min();
+            let (extended, pending): (Vec<_>, Vec<_>) =
+                st.pending.iter().cloned().partition(|&(_, start)| reach.is_some_and(|r| r <= start));
+            let events = extended.into_iter().map(|(m, _)| MoveEvent::Cancelled(m.name)).collect();
+            (EngineState { pending, ..st }, StepOutcome { events, ..StepOutcome::default() })
+        }
+        false => confirm(st),
+    };
+    let StepOutcome { moves, violations, events } = outcome;
+    let resolved = resolved.extend(StepOutcome { violations, events, ..StepOutcome::default() });
+    match automaton.goto_edges(st.cur_state).next() {
+        Some(_) => {
+            let held = moves.into_iter().map(|m| {
+                let start = depth.saturating_sub(m.steps);
+                (m, start)
+            });
+            let pending = st.pending.iter().cloned().chain(held).collect();
+            (EngineState { pending, ..st }, resolved)
+        }
+        None => {
+            let (st, confirmed) = confirm(st);
+            (st, resolved.extend(confirmed).extend(StepOutcome { moves, ..StepOutcome::default() }))
+        }
+    }
+}
+
+/* Moves fired by `ev`, along with combos refused for their timing and, in
+ * deferred mode, what became of held-back moves. Moves completed by a
+ * press that may still become a chord only come out once the chord
+ * window closes. */
+pub fn step_event_outcome(cfg: &EngineConfig, st: EngineState, ev: &InputEvent) -> (EngineState, StepOutcome) {
+    let step = |st: EngineState| match ev.kind {
+        KeyEventKind::Press => step_press(cfg, st, &ev.key, ev.time),
+        KeyEventKind::Release => step_release(cfg, st, &ev.key, ev.time),
+    };
+    if !cfg.deferred {
+        let (st, released, outcome) = step(st);
+        return (st, released.extend(outcome));
+    }
+    let (st, settled) = settle(cfg, st, ev.time);
+    let before = (st.cur_state, st.last_time_ms);
+    let (st, released, outcome) = step(st);
+    let (st, outcome) = defer(cfg, before, st, outcome);
+    (st, settled.extend(released).extend(outcome))
+}
+
+/* Timer entry point: call it between inputs so moves held back for a
+ * chord fire once the chord window closes, and, in deferred mode, moves
+ * held back for a longer combo once their step timeout runs out. */
+pub fn tick(cfg: &EngineConfig, st: EngineState, now_ms: u128) -> (EngineState, StepOutcome) {
+    let window_closed =
+        st.chord.as_ref().is_some_and(|g| now_ms.saturating_sub(g.start_ms) > cfg.chord_window.as_millis());
+    let (st, released) = if window_closed { close_chord(cfg, st) } else { (st, StepOutcome::default()) };
+    let (st, settled) = settle(cfg, st, now_ms);
+    (st, released.extend(settled))
+}
+
+/* End of input: held-back moves fire. */
+pub fn flush(cfg: &EngineConfig, st: EngineState) -> (EngineState, StepOutcome) {
+    let (st, released) = close_chord(cfg, st);
+    let (st, confirmed) = confirm(st);
+    (st, released.extend(confirmed))
+}
+
+pub fn step_event(cfg: &EngineConfig, st: EngineState, ev: &InputEvent) -> (EngineState, Vec<MoveHit>) {
+    let (st, outcome) = step_event_outcome(cfg, st, ev);
+    (st, outcome.moves)
+}
+
+/* Key-down only; what the terminal frontend can observe. */
 pub fn step_keytok(
     cfg: &EngineConfig,
     st: EngineState,
     keytok: &str,
     now_ms: u128,
-) -> (EngineState, Vec<String>) {
-    let internal = match cfg.key_to_internal.get(keytok) {
-        Some(s) => s.as_str(),
-        None => return (EngineState { cur_state: 0, last_time_ms: Some(now_ms) }, Vec::new()),
-    };
+) -> (EngineState, Vec<MoveHit>) {
+    step_event(cfg, st, &InputEvent::press(keytok, now_ms))
+}
 
-    let base_state = match st.last_time_ms {
-        Some(prev) if now_ms.saturating_sub(prev) > cfg.step_timeout.as_millis() as u128 => 0,
-        _ => st.cur_state,
-    };
+/* Drains `src`, returning the final state and every (time, move) fired.
+ * Moves still held back at the end fire at the last step's time. */
+pub fn run_events<S: EventSource + ?Sized>(cfg: &EngineConfig, st: EngineState, src: &mut S)
+    -> (EngineState, Vec<(u128, MoveHit)>)
+{
+    let mut st = st;
+    let mut fired = Vec::new();
+    while let Some(ev) = src.next_event() {
+        let (st2, outs) = step_event(cfg, st, &ev);
+        st = st2;
+        fired.extend(outs.into_iter().map(|m| (ev.time, m)));
+    }
+    let last = st.last_time_ms.unwrap_or(0);
+    let (st, rest) = flush(cfg, st);
+    fired.extend(rest.moves.into_iter().map(|m| (last, m)));
+    (st, fired)
+}
 
-    let (next, outs) = cfg.automaton.step(base_state, internal);
-    let new_state = EngineState { cur_state: next, last_time_ms: Some(now_ms) };
-    (new_state, outs)
+/* Same as `step_keytok`, reading the timestamp from `clock`. */
+pub fn step_keytok_with_clock<C: Clock + ?Sized>(
+    cfg: &EngineConfig,
+    st: EngineState,
+    keytok: &str,
+    clock: &C,
+) -> (EngineState, Vec<MoveHit>) {
+    step_keytok(cfg, st, keytok, clock.now_ms())
 }
 
-pub fn reset(_cfg: &EngineConfig, _st: EngineState) -> EngineState {
-    EngineState { cur_state: 0, last_time_ms: None }
+/* Swaps sides; a combo in progress carries on from the new side. */
+pub fn flip(_cfg: &EngineConfig, st: EngineState) -> EngineState {
+    EngineState { facing: st.facing.flipped(), ..st }
 }
 
-pub fn engine_from_gmr_file(path: &str, step_timeout: Duration)
-    -> Result<(EngineConfig, EngineState), String>
-{
-    let grammar = parse_gmr_file(path).map_err(|e| e.to_string())?;
+/* Back to the root; the active character and side are kept. */
+pub fn reset(_cfg: &EngineConfig, st: EngineState) -> EngineState {
+    EngineState {
+        cur_state: 0,
+        last_time_ms: None,
+        chord: None,
+        held: BTreeMap::new(),
+        history: Vec::new(),
+        pending: Vec::new(),
+        ..st
+    }
+}
+
+/* Parses and classifies `path`; discarded rules come back as warnings,
+ * or as an error in strict mode. */
+pub fn load_grammar(path: &str, opts: &EngineOptions) -> Result<(CompiledGrammar, Vec<Diagnostic>), String> {
+    let (grammar, diags) = parse_gmr_file_all(path).map_err(|e| format!("{path}: {e}"))?;
+    if has_errors(&diags) {
+        return Err(render_all(&diags));
+    }
     let compiled = classify(&grammar);
 
-    let bindings: Vec<(String, String)> =
-        compiled.bindings.iter().map(|b| (b.key.clone(), b.internal.clone())).collect();
+    let warnings: Vec<Diagnostic> = compiled.discarded.iter().map(|d| d.diagnostic()).collect();
+    if opts.strict && !warnings.is_empty() {
+        return Err(format!(
+            "{}\n{path}: {} rule(s) discarded, refusing to start in strict mode",
+            render_all(&warnings),
+            warnings.len(),
+        ));
+    }
+    Ok((compiled, warnings))
+}
 
-    Ok(build_engine(&compiled.combos, &bindings, step_timeout))
+/* `opts` with the grammar's `set` lines applied: shared ones first, then
+ * those of `player`'s own section. Options pinned on the command line win
+ * over both. Names and values were checked when the grammar was parsed
+ * (`check_setting`). */
+pub fn player_options(opts: &EngineOptions, settings: &[Setting], player: usize) -> EngineOptions {
+    let shared = settings.iter().filter(|s| s.player.is_none());
+    let own = settings.iter().filter(|s| s.player == Some(player));
+    shared.chain(own).fold(*opts, |o, s| {
+        let pinned = o.pinned;
+        match (s.name.as_str(), &s.value) {
+            ("timeout_ms", v) if !pinned.step_timeout => match v.parse() {
+                Ok(ms) => EngineOptions { step_timeout: Duration::from_millis(ms), ..o },
+                Err(_) => o,
+            },
+            ("on_timeout", v) if !pinned.on_timeout => match v.parse() {
+                Ok(on_timeout) => EngineOptions { on_timeout, ..o },
+                Err(_) => o,
+            },
+            ("on_unknown_key", v) if !pinned.on_unknown_key => match v.parse() {
+                Ok(on_unknown_key) => EngineOptions { on_unknown_key, ..o },
+                Err(_) => o,
+            },
+            ("resolution", v) if !pinned.resolution => match v.parse() {
+                Ok(resolution) => EngineOptions { resolution, ..o },
+                Err(_) => o,
+            },
+            _ => o,
+        }
+    })
 }
 
-pub fn current_state_info(cfg: &EngineConfig, st: EngineState) -> (Vec<String>, bool) {
-    let outputs = cfg.automaton.outputs_at(st.cur_state);
+/* The engine of one player (1-based): shared bindings plus the player's own. */
+pub fn engine_for_player(compiled: &CompiledGrammar, player: usize, opts: &EngineOptions)
+    -> (EngineConfig, EngineState)
+{
+    let bindings: Vec<(String, String)> = compiled
+        .bindings
+        .iter()
+        .filter(|b| b.player.is_none_or(|p| p == player))
+        .map(|b| (b.key.clone(), b.internal.clone()))
+        .collect();
+
+    build_engine(&compiled.combos, &bindings, &player_options(opts, &compiled.settings, player))
+}
+
+/* Player 1's engine; see `crate::versus` for every player at once. */
+pub fn engine_from_gmr_file(path: &str, opts: &EngineOptions)
+    -> Result<(EngineConfig, EngineState), String>
+{
+    let (compiled, warnings) = load_grammar(path, opts)?;
+    let (cfg, st) = engine_for_player(&compiled, 1, opts);
+    Ok((EngineConfig { warnings, ..cfg }, st))
+}
+
+pub fn current_state_info(cfg: &EngineConfig, st: &EngineState) -> (Vec<String>, bool) {
+    let dfa = &moveset(cfg, st).dfa;
+    let outputs = dfa.outputs(st.cur_state).iter().map(|&id| dfa.output_name(id).to_string()).collect();
     let is_fail = st.cur_state == 0 && st.last_time_ms.is_some();
     (outputs, is_fail)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::input::RecordedEvents;
+    use crate::parse::parse_gmr;
+
+    fn names(hits: &[MoveHit]) -> Vec<String> {
+        hits.iter().map(|m| m.name.clone()).collect()
+    }
+
+    fn engine_from_str(src: &str, step_timeout: Duration) -> (EngineConfig, EngineState) {
+        let compiled = classify(&parse_gmr(src).unwrap());
+        let bindings: Vec<(String, String)> =
+            compiled.bindings.iter().map(|b| (b.key.clone(), b.internal.clone())).collect();
+        let opts = EngineOptions { step_timeout, ..EngineOptions::default() };
+        build_engine(&compiled.combos, &bindings, &opts)
+    }
+
+    const GMR: &str = "
+        [BP], [FP] -> Active Duty
+        q -> [BP]
+        w -> [FP]
+    ";
+
+    #[test]
+    fn combo_within_timeout_fires() {
+        let (cfg, st) = engine_from_str(GMR, Duration::from_millis(500));
+        let clock = ManualClock::new(1_000);
+        let (st, outs) = step_keytok_with_clock(&cfg, st, "q", &clock);
+        assert!(outs.is_empty());
+        clock.advance(Duration::from_millis(499));
+        let (_, outs) = step_keytok_with_clock(&cfg, st, "w", &clock);
+        assert_eq!(names(&outs), vec!["Active Duty".to_string()]);
+    }
+
+    #[test]
+    fn gap_over_timeout_resets() {
+        let (cfg, st) = engine_from_str(GMR, Duration::from_millis(500));
+        let clock = ManualClock::new(1_000);
+        let (st, _) = step_keytok_with_clock(&cfg, st, "q", &clock);
+        clock.advance(Duration::from_millis(501));
+        let (st, outs) = step_keytok_with_clock(&cfg, st, "w", &clock);
+        assert!(outs.is_empty());
+        assert_eq!(st.cur_state, 0);
+    }
+
+    const CHORDS: &str = "
+        [BP]+[FP] -> Both
+        [Down], [BP]+[FP] -> Low Both
+        [BP] -> Single
+        q -> [BP]
+        w -> [FP]
+        s -> [Down]
+    ";
+
+    #[test]
+    fn presses_inside_chord_window_form_a_chord() {
+        let (cfg, st) = engine_from_str(CHORDS, Duration::from_millis(500));
+        let (st, outs) = step_keytok(&cfg, st, "q", 0);
+        assert!(outs.is_empty());
+        let (_, outs) = step_keytok(&cfg, st.clone(), "w", 30);
+        assert_eq!(names(&outs), vec!["Both".to_string()]);
+
+        /* no second key: the single press fires once the window closes */
+        let (st, outcome) = tick(&cfg, st, 50);
+        assert!(outcome.moves.is_empty());
+        let (st, outcome) = tick(&cfg, st, 51);
+        assert_eq!(outcome.move_names(), vec!["Single".to_string()]);
+        assert_eq!(tick(&cfg, st, 52).1, StepOutcome::default());
+    }
+
+    #[test]
+    fn chord_order_does_not_matter_and_keeps_prefix() {
+        let (cfg, st) = engine_from_str(CHORDS, Duration::from_millis(500));
+        let (st, _) = step_keytok(&cfg, st, "s", 0);
+        let (st, _) = step_keytok(&cfg, st, "w", 200);
+        let (_, outs) = step_keytok(&cfg, st, "q", 210);
+        assert!(names(&outs).contains(&"Low Both".to_string()));
+        assert!(names(&outs).contains(&"Both".to_string()));
+    }
+
+    #[test]
+    fn presses_outside_chord_window_stay_separate() {
+        let (cfg, st) = engine_from_str(CHORDS, Duration::from_millis(500));
+        let (st, _) = step_keytok(&cfg, st, "q", 0);
+        let (_, outs) = step_keytok(&cfg, st, "w", 51);
+        assert_eq!(names(&outs), vec!["Single".to_string()]);
+    }
+
+    const CHARGE: &str = "
+        [Down], [Back]~800, [Fwd], [FP] -> Sonic Boom
+        ^[FP] -> Negative Edge
+        left -> [Back]
+        right -> [Fwd]
+        s -> [Down]
+        w -> [FP]
+    ";
+
+    fn fired(src: &str, events: &str) -> Vec<String> {
+        let (cfg, st) = engine_from_str(src, Duration::from_millis(500));
+        let mut events = RecordedEvents::parse(events).unwrap();
+        run_events(&cfg, st, &mut events).1.into_iter().map(|(_, m)| m.name).collect()
+    }
+
+    #[test]
+    fn charge_move_needs_the_full_hold() {
+        let events = "0 press s\n100 release s\n200 press left\n1000 release left\n1100 press right\n1200 press w\n";
+        assert_eq!(fired(CHARGE, events), vec!["Sonic Boom".to_string()]);
+        let short = "0 press s\n100 release s\n200 press left\n900 release left\n1000 press right\n1100 press w\n";
+        assert!(!fired(CHARGE, short).contains(&"Sonic Boom".to_string()));
+    }
+
+    #[test]
+    fn release_steps_fire_on_key_up() {
+        let events = "0 press w\n40 release w\n";
+        assert_eq!(fired(CHARGE, events), vec!["Negative Edge".to_string()]);
+    }
+
+    #[test]
+    fn unused_releases_do_not_reset() {
+        let (cfg, st) = engine_from_str(GMR, Duration::from_millis(500));
+        let (st, _) = step_event(&cfg, st, &InputEvent::press("q", 0));
+        let (st, _) = step_event(&cfg, st, &InputEvent::release("q", 50));
+        let (_, outs) = step_event(&cfg, st, &InputEvent::press("w", 100));
+        assert_eq!(names(&outs), vec!["Active Duty".to_string()]);
+    }
+
+    #[test]
+    fn strict_mode_refuses_discarded_rules() {
+        let path = "grammar/errors/discarded.gmr";
+        let (cfg, _) = engine_from_gmr_file(path, &EngineOptions::default()).unwrap();
+        assert_eq!(cfg.warnings.len(), 4);
+        let strict = EngineOptions { strict: true, ..EngineOptions::default() };
+        let err = engine_from_gmr_file(path, &strict).unwrap_err();
+        assert!(err.contains("refusing to start in strict mode"));
+        assert!(engine_from_gmr_file("grammar/test.gmr", &strict).is_ok());
+    }
+
+    #[test]
+    fn multi_file_grammar_builds_one_engine() {
+        let (cfg, st) = engine_from_gmr_file("grammar/roster/main.gmr", &EngineOptions::default()).unwrap();
+        assert!(cfg.warnings.is_empty());
+        assert_eq!(cfg.key_to_internal.len(), 5);
+        let (st, _) = step_keytok(&cfg, st, "down", 0);
+        let (st, _) = step_keytok(&cfg, st, "right", 100);
+        let (_, moves) = step_keytok(&cfg, st, "w", 200);
+        assert_eq!(names(&moves), vec!["Fireball (Generic)".to_string()]);
+    }
+
+    #[test]
+    fn listing_shows_compact_form() {
+        let (cfg, st) = engine_from_str(
+            "[Left]*3, ([FP] | [BP]) -> Finisher\nq -> [BP]\nw -> [FP]\nleft -> [Left]",
+            Duration::from_millis(500),
+        );
+        let views = combo_views(&cfg, &st);
+        assert_eq!(combos_internal(&cfg, &st).len(), 2);
+        assert_eq!(views.len(), 1);
+        assert_eq!(views[0].variants.len(), 2);
+        assert!(format_engine_info(&cfg, &st).contains("[Left]*3, ([FP] | [BP])\nFinisher !!\n"));
+        assert_eq!(display_for_combo(&cfg, &st, &views[0]), "left*3, (w | q)");
+    }
+
+    #[test]
+    fn listing_shows_macros_or_their_steps() {
+        let src = "let QCF = [Down], [Right]\n$QCF*2, [FP] -> Fireball\ns -> [Down]\nd -> [Right]\nw -> [FP]";
+        let (mut cfg, st) = engine_from_str(src, Duration::from_millis(500));
+        assert!(format_engine_info(&cfg, &st).contains("$QCF*2, [FP]\nFireball !!\n"));
+        assert_eq!(display_for_combo(&cfg, &st, &combo_views(&cfg, &st)[0]), "$QCF*2, w");
+        cfg.expand_macros = true;
+        assert!(format_engine_info(&cfg, &st).contains("([Down], [Right])*2, [FP]\nFireball !!\n"));
+        assert_eq!(display_for_combo(&cfg, &st, &combo_views(&cfg, &st)[0]), "(s, d)*2, w");
+    }
+
+    const FACING: &str = "
+        [Down], [DownFwd], [Fwd], [FP] -> Hadouken
+        [Left], [Left] -> Back Dash
+        left -> [Left]
+        right -> [Right]
+        down -> [Down]
+        w -> [FP]
+        3 -> [DownRight]
+        1 -> [DownLeft]
+    ";
+
+    #[test]
+    fn directions_follow_facing() {
+        let (cfg, st) = engine_from_str(FACING, Duration::from_millis(500));
+        let hadouken = ["down", "3", "right", "w"];
+        let press_all = |st: EngineState, keys: &[&str]| {
+            keys.iter().enumerate().fold((st, Vec::new()), |(st, mut fired), (i, k)| {
+                let (st, outs) = step_keytok(&cfg, st, k, i as u128 * 100);
+                fired.extend(names(&outs));
+                (st, fired)
+            })
+        };
+        assert_eq!(press_all(st.clone(), &hadouken).1, vec!["Hadouken".to_string()]);
+
+        let flipped = flip(&cfg, st.clone());
+        assert_eq!(flipped.facing, Facing::Left);
+        assert!(press_all(flipped.clone(), &hadouken).1.is_empty());
+        assert_eq!(press_all(flipped.clone(), &["down", "1", "left", "w"]).1, vec!["Hadouken".to_string()]);
+        /* written as [Left], i.e. back: pressed as right once flipped */
+        assert_eq!(press_all(flipped.clone(), &["right", "right"]).1, vec!["Back Dash".to_string()]);
+
+        let views = combo_views(&cfg, &st);
+        assert_eq!(display_for_combo(&cfg, &st, &views[0]), "down, 3, right, w");
+        assert_eq!(display_for_combo(&cfg, &flipped, &views[0]), "down, 1, left, w");
+        assert_eq!(display_for_combo(&cfg, &flipped, &views[1]), "right, right");
+        assert_eq!(flip(&cfg, flipped).facing, Facing::Right);
+    }
+
+    #[test]
+    fn step_labels_keep_absolute_keys_first() {
+        let src = "[Back], [BP] -> Retreat\nleft -> [Left]\nb -> [Back]\nq -> [BP]\np -> [BP]";
+        let (cfg, st) = engine_from_str(src, Duration::from_millis(500));
+        assert_eq!(display_for_internal(&cfg, &st, "[Back]"), "left / b");
+        assert_eq!(display_for_internal(&cfg, &st, "[BP]"), "p / q");
+        assert_eq!(display_for_internal(&cfg, &flip(&cfg, st), "[Back]"), "b");
+    }
+
+    const TIMED: &str = "
+        [BP], [FP] -> Quick {max_gap=100}
+        [BK], [FK] -> Slow {max_gap=1000}
+        [BP], [BK], [FP] -> Rhythm {min_gap=50, max_total=300}
+        q -> [BP]
+        w -> [FP]
+        a -> [BK]
+        s -> [FK]
+    ";
+
+    fn outcome_of(presses: &[(&str, u128)]) -> StepOutcome {
+        let (cfg, st) = engine_from_str(TIMED, Duration::from_millis(500));
+        presses
+            .iter()
+            .fold((st, StepOutcome::default()), |(st, outcome), &(key, t)| {
+                let (st, more) = step_event_outcome(&cfg, st, &InputEvent::press(key, t));
+                (st, outcome.extend(more))
+            })
+            .1
+    }
+
+    #[test]
+    fn timing_windows_are_enforced_per_combo() {
+        let missed = |name: &str, constraint| Violation { move_name: name.to_string(), constraint };
+
+        assert_eq!(outcome_of(&[("q", 0), ("w", 80)]).move_names(), vec!["Quick".to_string()]);
+        let late = outcome_of(&[("q", 0), ("w", 150)]);
+        assert!(late.moves.is_empty());
+        assert_eq!(late.violations, vec![missed("Quick", Constraint::MaxGap { step: 2, gap: 150, limit: 100 })]);
+
+        /* looser than the 500ms step timeout */
+        assert_eq!(outcome_of(&[("a", 0), ("s", 900)]).move_names(), vec!["Slow".to_string()]);
+        let overdue = outcome_of(&[("a", 0), ("s", 1100)]);
+        assert!(overdue.moves.is_empty());
+        assert_eq!(overdue.violations, vec![missed("Slow", Constraint::MaxGap { step: 2, gap: 1100, limit: 1000 })]);
+        /* reached through the failure link of Rhythm's [BP], [BK] */
+        assert_eq!(outcome_of(&[("q", 0), ("a", 100), ("s", 1000)]).move_names(), vec!["Slow".to_string()]);
+
+        assert_eq!(outcome_of(&[("q", 0), ("a", 100), ("w", 200)]).move_names(), vec!["Rhythm".to_string()]);
+        let rushed = outcome_of(&[("q", 0), ("a", 20), ("w", 200)]);
+        assert_eq!(rushed.violations, vec![missed("Rhythm", Constraint::MinGap { step: 2, gap: 20, limit: 50 })]);
+        let slow = outcome_of(&[("q", 0), ("a", 200), ("w", 400)]);
+        assert_eq!(slow.violations, vec![missed("Rhythm", Constraint::MaxTotal { total: 400, limit: 300 })]);
+        assert_eq!(slow.violations[0].constraint.to_string(), "took 400ms in total (max_total=300)");
+    }
+
+    const POLICIES: &str = "
+        [BP], [FP], [BK] -> Triple
+        [FP], [BK] -> Pair
+        [BP], [?], [FP] -> Feint
+        q -> [BP]
+        w -> [FP]
+        a -> [BK]
+    ";
+
+    fn fired_with(opts: EngineOptions, presses: &[(&str, u128)]) -> Vec<String> {
+        let compiled = classify(&parse_gmr(POLICIES).unwrap());
+        let (cfg, st) = engine_for_player(&compiled, 1, &opts);
+        presses
+            .iter()
+            .fold((st, Vec::new()), |(st, mut fired), &(key, t)| {
+                let (st, outs) = step_keytok(&cfg, st, key, t);
+                fired.extend(names(&outs));
+                (st, fired)
+            })
+            .1
+    }
+
+    #[test]
+    fn timeout_policies() {
+        let late = [("q", 0), ("w", 100), ("a", 700)];
+        let with = |on_timeout| fired_with(EngineOptions { on_timeout, ..EngineOptions::default() }, &late);
+        assert!(with(TimeoutPolicy::Reset).is_empty());
+        /* [BP], [FP] falls back to [FP], which [BK] completes */
+        assert_eq!(with(TimeoutPolicy::FailureLink), vec!["Pair".to_string()]);
+        assert!(with(TimeoutPolicy::Keep).contains(&"Triple".to_string()));
+    }
+
+    #[test]
+    fn unknown_key_policies() {
+        let noisy = [("q", 0), ("x", 50), ("w", 100), ("a", 150)];
+        let with = |on_unknown_key| fired_with(EngineOptions { on_unknown_key, ..EngineOptions::default() }, &noisy);
+        assert_eq!(with(UnknownKeyPolicy::Reset), vec!["Pair".to_string()]);
+        assert!(with(UnknownKeyPolicy::Ignore).contains(&"Triple".to_string()));
+        assert_eq!(with(UnknownKeyPolicy::Noise), vec!["Feint".to_string(), "Pair".to_string()]);
+    }
+
+    #[test]
+    fn policies_come_from_grammar_settings() {
+        let src = "set on_timeout = keep\n[player 2]\nset on_unknown_key = noise\nq -> [BP]";
+        let compiled = classify(&parse_gmr(src).unwrap());
+        let p1 = player_options(&EngineOptions::default(), &compiled.settings, 1);
+        let p2 = player_options(&EngineOptions::default(), &compiled.settings, 2);
+        assert_eq!((p1.on_timeout, p1.on_unknown_key), (TimeoutPolicy::Keep, UnknownKeyPolicy::Reset));
+        assert_eq!((p2.on_timeout, p2.on_unknown_key), (TimeoutPolicy::Keep, UnknownKeyPolicy::Noise));
+
+        /* a command-line flag wins over the grammar */
+        let pinned = Pinned { on_timeout: true, ..Pinned::default() };
+        let flags = EngineOptions { on_timeout: TimeoutPolicy::FailureLink, pinned, ..EngineOptions::default() };
+        let p2 = player_options(&flags, &compiled.settings, 2);
+        assert_eq!((p2.on_timeout, p2.on_unknown_key), (TimeoutPolicy::FailureLink, UnknownKeyPolicy::Noise));
+
+        /* bad values never get this far */
+        assert!(parse_gmr("set on_unknown_key = sometimes\nq -> [BP]").is_err());
+    }
+
+    const DEFERRED: &str = "
+        [FP], [FP] -> Test state
+        [FP], [FP], [BP] -> Super
+        w -> [FP]
+        q -> [BP]
+    ";
+
+    #[test]
+    fn deferred_mode_waits_for_the_longest_combo() {
+        let (cfg, st) = engine_from_str(DEFERRED, Duration::from_millis(500));
+        let cfg = EngineConfig { deferred: true, ..cfg };
+        let press = |st: EngineState, key: &str, t: u128| step_event_outcome(&cfg, st, &InputEvent::press(key, t));
+
+        let (st, _) = press(st, "w", 0);
+        let (held, outcome) = press(st, "w", 100);
+        assert!(outcome.moves.is_empty());
+        assert_eq!(held.pending, vec![(MoveHit { name: "Test state".to_string(), source: MatchSource::Direct, info: MoveInfo::default(), steps: 2 }, 0)]);
+
+        let (_, outcome) = press(held.clone(), "q", 200);
+        assert_eq!(outcome.move_names(), vec!["Super".to_string()]);
+        assert_eq!(outcome.events, vec![MoveEvent::Cancelled("Test state".to_string())]);
+
+        /* a third [FP] cannot grow the combo: the held move fires, and the new one waits */
+        let (st, outcome) = press(held.clone(), "w", 200);
+        assert_eq!(outcome.move_names(), vec!["Test state".to_string()]);
+        assert_eq!(outcome.events, vec![MoveEvent::Confirmed("Test state".to_string())]);
+        assert_eq!(st.pending, vec![(MoveHit { name: "Test state".to_string(), source: MatchSource::Direct, info: MoveInfo::default(), steps: 2 }, 0)]);
+
+        let (held, outcome) = tick(&cfg, held, 600);
+        assert_eq!(outcome, StepOutcome::default());
+        let (held, outcome) = tick(&cfg, held, 601);
+        assert_eq!(outcome.move_names(), vec!["Test state".to_string()]);
+        assert!(held.pending.is_empty());
+    }
+
+    #[test]
+    fn deferred_mode_cancels_only_the_moves_a_longer_combo_covers() {
+        let grammar = "
+            [BP], [FP] -> Pair
+            [FP], [BK] -> Link
+            [BP], [FP], [BK], [FK] -> Long
+            q -> [BP]
+            w -> [FP]
+            a -> [BK]
+            s -> [FK]
+        ";
+        let (cfg, st) = engine_from_str(grammar, Duration::from_millis(500));
+        let cfg = EngineConfig { deferred: true, ..cfg };
+        let press = |st: EngineState, key: &str, t: u128| step_event_outcome(&cfg, st, &InputEvent::press(key, t));
+
+        let (st, _) = press(st, "q", 0);
+        let (st, _) = press(st, "w", 100);
+        /* Link starts after Pair's first step, so Pair keeps waiting */
+        let (held, outcome) = press(st, "a", 200);
+        assert_eq!(outcome, StepOutcome::default());
+        let waiting: Vec<&str> = held.pending.iter().map(|(m, _)| m.name.as_str()).collect();
+        assert_eq!(waiting, vec!["Pair", "Link"]);
+
+        let (_, outcome) = press(held.clone(), "s", 300);
+        assert_eq!(outcome.move_names(), vec!["Long".to_string()]);
+        assert_eq!(outcome.events, vec![MoveEvent::Cancelled("Pair".to_string()), MoveEvent::Cancelled("Link".to_string())]);
+
+        let (_, outcome) = flush(&cfg, held);
+        assert_eq!(outcome.move_names(), vec!["Pair".to_string(), "Link".to_string()]);
+    }
+
+    #[test]
+    fn deferred_moves_fire_at_the_end_of_a_recording() {
+        let (cfg, st) = engine_from_str(DEFERRED, Duration::from_millis(500));
+        let mut events = RecordedEvents::parse("0 press w\n100 press w\n").unwrap();
+        let (_, fired) = run_events(&EngineConfig { deferred: true, ..cfg.clone() }, st.clone(), &mut events);
+        assert_eq!(fired.into_iter().map(|(t, m)| (t, m.name)).collect::<Vec<_>>(), vec![(100, "Test state".to_string())]);
+        /* immediate mode is unchanged */
+        let (_, outs) = step_keytok(&cfg, step_keytok(&cfg, st, "w", 0).0, "w", 100);
+        assert_eq!(names(&outs), vec!["Test state".to_string()]);
+    }
+
+    const CLASHING: &str = "
+        [BP], [FP] -> Slide {priority=1}
+        [BP], [FP] -> Slide2 {priority=5}
+        [FP] -> Jab {priority=5}
+        q -> [BP]
+        w -> [FP]
+    ";
+
+    #[test]
+    fn resolution_policies_pick_and_order_moves() {
+        use MatchSource::{Direct, Suffix};
+        let compiled = classify(&parse_gmr(CLASHING).unwrap());
+        let hits = |resolution| {
+            let (cfg, st) = engine_for_player(&compiled, 1, &EngineOptions { resolution, ..EngineOptions::default() });
+            let (st, _) = step_event_outcome(&cfg, st, &InputEvent::press("q", 0));
+            let (_, outcome) = step_event_outcome(&cfg, st, &InputEvent::press("w", 10));
+            outcome.moves.into_iter().map(|m| (m.name, m.source)).collect::<Vec<_>>()
+        };
+        let hit = |name: &str, source| (name.to_string(), source);
+
+        assert_eq!(hits(ResolutionPolicy::All), vec![hit("Slide", Direct), hit("Slide2", Direct), hit("Jab", Suffix)]);
+        assert_eq!(hits(ResolutionPolicy::HighestPriority), vec![hit("Slide2", Direct), hit("Jab", Suffix)]);
+        assert_eq!(hits(ResolutionPolicy::LongestMatch), vec![hit("Slide", Direct), hit("Slide2", Direct)]);
+        assert_eq!(hits(ResolutionPolicy::FirstDeclared), vec![hit("Slide", Direct)]);
+
+        let with_setting = classify(&parse_gmr(&format!("set resolution = longest_match\n{CLASHING}")).unwrap());
+        let (cfg, _) = engine_for_player(&with_setting, 1, &EngineOptions::default());
+        assert_eq!(cfg.resolution, ResolutionPolicy::LongestMatch);
+    }
+
+    #[test]
+    fn moves_carry_their_metadata() {
+        let src = "[BP] -> Fireball {character=Generic, damage=12, startup=9, tags=projectile}\nq -> [BP]";
+        let (cfg, st) = engine_from_str(src, Duration::from_millis(500));
+        assert!(format_engine_info(&cfg, &st).contains("Fireball (Generic, 12 dmg, startup 9f, projectile) !!"));
+        let (_, hits) = step_keytok(&cfg, st, "q", 0);
+        assert_eq!(hits[0].info.damage, Some(12));
+        assert_eq!(hits[0].info.tags, vec!["projectile".to_string()]);
+        assert!("tag=projectile,startup<10".parse::<crate::attrs::MoveFilter>().unwrap().matches(&hits[0].info));
+    }
+
+    const ROSTER: &str = "\
+q -> [BP]
+w -> [FP]
+[BP], [FP] -> Shared Jab
+[character \"Jax\"]
+[FP], [FP] -> Gotcha Grab
+[character \"Kano\"]
+[FP], [FP] -> Knife Throw
+[BP], [BP] -> Cannonball
+";
+
+    #[test]
+    fn characters_have_their_own_moveset() {
+        let (cfg, st) = engine_from_str(ROSTER, Duration::from_millis(500));
+        assert_eq!(character_names(&cfg), vec!["Jax", "Kano"]);
+        let listed = |st: &EngineState| -> Vec<String> {
+            combo_views(&cfg, st).iter().map(|v| v.move_name.clone()).collect()
+        };
+        assert_eq!(listed(&st), vec!["Shared Jab", "Gotcha Grab"]);
+        assert!(format_engine_info(&cfg, &st).starts_with("Character: Jax (1/2)\n"));
+
+        let (st, _) = step_keytok(&cfg, st, "w", 0);
+        let (jax, outs) = step_keytok(&cfg, st.clone(), "w", 100);
+        assert_eq!(names(&outs), vec!["Gotcha Grab".to_string()]);
+
+        /* switching drops the half-entered combo */
+        let kano = next_character(&cfg, st);
+        assert_eq!((kano.character, kano.cur_state), (1, 0));
+        assert_eq!(listed(&kano), vec!["Shared Jab", "Knife Throw", "Cannonball"]);
+        let (kano, _) = step_keytok(&cfg, kano, "w", 200);
+        let (kano, outs) = step_keytok(&cfg, kano, "w", 300);
+        assert_eq!(names(&outs), vec!["Knife Throw".to_string()]);
+
+        assert_eq!(next_character(&cfg, kano).character, 0);
+        assert_eq!(select_character(&cfg, jax.clone(), "Kano").map(|s| s.character), Some(1));
+        assert!(select_character(&cfg, jax, "Scorpion").is_none());
+    }
+}
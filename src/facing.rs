@@ -0,0 +1,102 @@
+use crate::parse::{chord_members, chord_token, hold_token, release_token, split_step, StepKind};
+
+/* Which way a player faces. Combos are written from the left side of the
+ * screen (facing right), where forward is [Right]. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Facing {
+    #[default]
+    Right,
+    Left,
+}
+
+impl Facing {
+    pub fn flipped(self) -> Self {
+        match self {
+            Facing::Right => Facing::Left,
+            Facing::Left => Facing::Right,
+        }
+    }
+}
+
+/* (absolute, relative) when facing right */
+const DIRECTIONS: &[(&str, &str)] = &[
+    ("[Right]", "[Fwd]"),
+    ("[Left]", "[Back]"),
+    ("[DownRight]", "[DownFwd]"),
+    ("[DownLeft]", "[DownBack]"),
+    ("[UpRight]", "[UpFwd]"),
+    ("[UpLeft]", "[UpBack]"),
+];
+
+fn mirror(abs: &str) -> Option<&'static str> {
+    let (a, b) = match abs {
+        "[Right]" | "[Left]" => ("[Right]", "[Left]"),
+        "[DownRight]" | "[DownLeft]" => ("[DownRight]", "[DownLeft]"),
+        "[UpRight]" | "[UpLeft]" => ("[UpRight]", "[UpLeft]"),
+        _ => return None,
+    };
+    Some(if abs == a { b } else { a })
+}
+
+pub fn is_relative(internal: &str) -> bool {
+    DIRECTIONS.iter().any(|&(_, rel)| rel == internal)
+}
+
+/* `[Left]` -> `[Back]` facing right, `[Fwd]` facing left; anything that is
+ * not a horizontal direction comes back unchanged. */
+pub fn to_relative(internal: &str, facing: Facing) -> String {
+    let abs = match facing {
+        Facing::Right => Some(internal),
+        Facing::Left => mirror(internal),
+    };
+    abs.and_then(|a| DIRECTIONS.iter().find(|&&(x, _)| x == a))
+        .map_or_else(|| internal.to_string(), |&(_, rel)| rel.to_string())
+}
+
+/* `[Fwd]` -> `[Right]` facing right, `[Left]` facing left. */
+pub fn to_absolute(internal: &str, facing: Facing) -> String {
+    match DIRECTIONS.iter().find(|&&(_, rel)| rel == internal) {
+        Some(&(abs, _)) if facing == Facing::Left => mirror(abs).unwrap_or(abs).to_string(),
+        Some(&(abs, _)) => abs.to_string(),
+        None => internal.to_string(),
+    }
+}
+
+/* Applies `f` to every plain member of a step token, keeping chords,
+ * releases and holds in shape: "^[Left]" -> "^" + f("[Left]"). */
+pub fn map_step(tok: &str, f: &dyn Fn(&str) -> String) -> String {
+    let members = chord_members(tok);
+    if members.len() > 1 {
+        return chord_token(&members.iter().map(|m| map_step(m, f)).collect::<Vec<_>>());
+    }
+    match split_step(tok) {
+        (StepKind::Release, base) => release_token(&f(base)),
+        (StepKind::Hold(ms), base) => hold_token(&f(base), ms),
+        (StepKind::Press, base) => f(base),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directions_follow_the_side() {
+        assert_eq!(to_relative("[Left]", Facing::Right), "[Back]");
+        assert_eq!(to_relative("[Left]", Facing::Left), "[Fwd]");
+        assert_eq!(to_relative("[DownRight]", Facing::Left), "[DownBack]");
+        assert_eq!(to_relative("[Down]", Facing::Left), "[Down]");
+        assert_eq!(to_relative("[Fwd]", Facing::Left), "[Fwd]");
+        assert_eq!(to_absolute("[UpFwd]", Facing::Left), "[UpLeft]");
+        assert_eq!(to_absolute("[Back]", Facing::Right), "[Left]");
+        assert!(is_relative("[DownBack]") && !is_relative("[Down]"));
+    }
+
+    #[test]
+    fn steps_keep_their_shape() {
+        let rel = |m: &str| to_relative(m, Facing::Right);
+        assert_eq!(map_step("^[Right]", &rel), "^[Fwd]");
+        assert_eq!(map_step("[Left]~800", &rel), "[Back]~800");
+        assert_eq!(map_step("[Right]+[BP]", &rel), "[BP]+[Fwd]");
+    }
+}
@@ -0,0 +1,182 @@
+use crate::diag::{render_all, Diagnostic, SourceLoc};
+use crate::engine::{engine_from_gmr_file, step_keytok, EngineConfig, EngineOptions, EngineState};
+
+/* One line of a `.gmrtest` file: a key token, when it is pressed, and the
+ * moves that must fire on it, in order. Text form:
+ *
+ *   # comment
+ *   0    down
+ *   100  right
+ *   200  w     => Fireball (Generic)
+ *   900  q     => Claw Slam | Uppercut
+ *
+ * A line without `=>` expects no move; times are in ms and never go back. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expectation {
+    pub time: u128,
+    pub key: String,
+    pub moves: Vec<String>,
+    pub loc: SourceLoc,
+}
+
+pub fn parse_gmrtest(text: &str, file: Option<&str>) -> Result<Vec<Expectation>, Vec<Diagnostic>> {
+    let mut last_time = 0;
+    let (cases, errors): (Vec<_>, Vec<_>) = text
+        .lines()
+        .enumerate()
+        .map(|(idx, raw)| (idx + 1, raw))
+        .filter(|(_, raw)| !raw.trim().is_empty() && !raw.trim_start().starts_with('#'))
+        .map(|(line_no, raw)| {
+            let error = |reason: &str| Diagnostic::error(SourceLoc::whole_line(file, line_no, raw), reason);
+            let (input, expected) = match raw.split_once("=>") {
+                Some((input, expected)) => (input, Some(expected)),
+                None => (raw, None),
+            };
+            let mut parts = input.split_whitespace();
+            let time = parts
+                .next()
+                .and_then(|t| t.parse::<u128>().ok())
+                .ok_or_else(|| error("expected a timestamp in ms"))?;
+            let key = parts.next().ok_or_else(|| error("missing key token"))?;
+            if parts.next().is_some() {
+                return Err(error("one key token per line (expected `<time_ms> <key> [=> Move | ...]`)"));
+            }
+            if time < last_time {
+                return Err(error(&format!("time goes back ({time}ms after {last_time}ms)")));
+            }
+            last_time = time;
+            let moves: Vec<String> = expected
+                .map(|e| e.split('|').map(str::trim).map(str::to_string).collect())
+                .unwrap_or_default();
+            if moves.iter().any(String::is_empty) {
+                return Err(error("empty move name after `=>`"));
+            }
+            Ok(Expectation { time, key: key.to_string(), moves, loc: SourceLoc::whole_line(file, line_no, raw) })
+        })
+        .partition(Result::is_ok);
+    if errors.is_empty() {
+        Ok(cases.into_iter().map(Result::unwrap).collect())
+    } else {
+        Err(errors.into_iter().map(Result::unwrap_err).collect())
+    }
+}
+
+/* An expectation the engine did not meet, with what fired instead. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub expected: Expectation,
+    pub got: Vec<String>,
+}
+
+impl Mismatch {
+    /*
+     * error: wrong moves after `w` at 200ms
+     *  --> grammar/test.gmrtest:7:1
+     *   |
+     * 7 | 200  w     => Fireball (Generic)
+     *   | ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^
+     *   - Fireball (Generic)
+     *   + Claw Slam (Freddy Krueger)
+     */
+    pub fn render(&self) -> String {
+        let e = &self.expected;
+        let message = format!("wrong moves after `{}` at {}ms", e.key, e.time);
+        let pad = " ".repeat(e.loc.line.to_string().len());
+        let missing = e.moves.iter().filter(|m| !self.got.contains(m)).map(|m| format!("{pad} - {m}\n"));
+        let extra = self.got.iter().filter(|m| !e.moves.contains(m)).map(|m| format!("{pad} + {m}\n"));
+        let diff: String = missing.chain(extra).collect();
+        /* same moves, other order */
+        let diff = if diff.is_empty() {
+            format!("{pad} - {}\n{pad} + {}\n", e.moves.join(" | "), self.got.join(" | "))
+        } else {
+            diff
+        };
+        format!("{}{diff}", Diagnostic::error(e.loc.clone(), message).render())
+    }
+}
+
+/* Feeds every key through `engine::step_keytok`, from `st`, and compares
+ * the moves fired on each step with the expected ones. */
+pub fn run_gmrtest(cfg: &EngineConfig, st: EngineState, cases: &[Expectation]) -> Vec<Mismatch> {
+    let (_, mismatches) = cases.iter().fold((st, Vec::new()), |(st, mut mismatches), case| {
+        let (st, hits) = step_keytok(cfg, st, &case.key, case.time);
+        let got: Vec<String> = hits.into_iter().map(|m| m.name).collect();
+        if got != case.moves {
+            mismatches.push(Mismatch { expected: case.clone(), got });
+        }
+        (st, mismatches)
+    });
+    mismatches
+}
+
+/* Loads both files; returns the number of steps checked and what failed. */
+pub fn run_gmrtest_files(gmr: &str, gmrtest: &str) -> Result<(usize, Vec<Mismatch>), String> {
+    let (cfg, st) = engine_from_gmr_file(gmr, &EngineOptions::default())?;
+    let text = std::fs::read_to_string(gmrtest).map_err(|e| format!("{gmrtest}: {e}"))?;
+    let cases = parse_gmrtest(&text, Some(gmrtest)).map_err(|diags| render_all(&diags))?;
+    Ok((cases.len(), run_gmrtest(&cfg, st, &cases)))
+}
+
+/* For `cargo test`: panics with every mismatch if `gmrtest` fails against
+ * `gmr`. Paths are relative to the crate root.
+ *
+ *   #[test]
+ *   fn moveset() { ft_ality::gmrtest::assert_gmrtest("grammar/test.gmr", "grammar/test.gmrtest"); }
+ */
+pub fn assert_gmrtest(gmr: &str, gmrtest: &str) {
+    match run_gmrtest_files(gmr, gmrtest) {
+        Ok((_, mismatches)) if mismatches.is_empty() => {}
+        Ok((steps, mismatches)) => {
+            let report: Vec<String> = mismatches.iter().map(Mismatch::render).collect();
+            panic!("{gmrtest}: {} of {steps} step(s) failed\n\n{}", mismatches.len(), report.join("\n"));
+        }
+        Err(e) => panic!("{e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::build_engine;
+    use crate::parse::{classify, parse_gmr};
+
+    #[test]
+    fn bundled_golden_file_passes() {
+        assert_gmrtest("grammar/test.gmr", "grammar/test.gmrtest");
+    }
+
+    #[test]
+    fn parses_lines_and_reports_bad_ones() {
+        let cases = parse_gmrtest("# c\n0 down\n\n100 w => A | B (Jax)\n", Some("t.gmrtest")).unwrap();
+        assert_eq!(cases.len(), 2);
+        assert_eq!((cases[0].time, cases[0].key.as_str(), cases[0].moves.len()), (0, "down", 0));
+        assert_eq!(cases[1].moves, vec!["A", "B (Jax)"]);
+        assert_eq!(cases[1].loc.line, 4);
+
+        let errors = parse_gmrtest("w\n100 w\n50 w\n160 a b\n170 w =>\n", None).unwrap_err();
+        let messages: Vec<(usize, &str)> = errors.iter().map(|d| (d.loc.line, d.message.as_str())).collect();
+        assert_eq!(messages, vec![
+            (1, "expected a timestamp in ms"),
+            (3, "time goes back (50ms after 100ms)"),
+            (4, "one key token per line (expected `<time_ms> <key> [=> Move | ...]`)"),
+            (5, "empty move name after `=>`"),
+        ]);
+    }
+
+    #[test]
+    fn mismatches_render_as_diffs() {
+        let g = classify(&parse_gmr("q -> [BP]\nw -> [FP]\n[BP] -> Jab\n[BP], [FP] -> Combo\n").unwrap());
+        let bindings: Vec<(String, String)> = g.bindings.iter().map(|b| (b.key.clone(), b.internal.clone())).collect();
+        let (cfg, st) = build_engine(&g.combos, &bindings, &EngineOptions::default());
+
+        let cases = parse_gmrtest("0 q => Jab\n100 w => Uppercut\n200 w\n300 q\n", Some("t.gmrtest")).unwrap();
+        let found = run_gmrtest(&cfg, st, &cases);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].got, vec!["Combo"]);
+        assert_eq!(
+            found[0].render(),
+            "error: wrong moves after `w` at 100ms\n --> t.gmrtest:2:1\n  |\n2 | 100 w => Uppercut\n  | ^^^^^^^^^^^^^^^^^\n  - Uppercut\n  + Combo\n",
+        );
+        assert!(found[1].render().ends_with("  + Jab\n"));
+    }
+}
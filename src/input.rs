@@ -42,7 +42,7 @@ where
             return None;
         }
         let b = next(timeout)?;
-        if (b'A'..=b'Z').contains(&b) || b"~@".contains(&b) {
+        if b.is_ascii_uppercase() || b"~@".contains(&b) {
             Some((acc, b))
         } else {
             acc.push(b as char);
@@ -131,28 +131,212 @@ where
     None
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyEventKind {
+    Press,
+    Release,
+}
+
+/* One key transition, as fed to `engine::step_event`. `time` is in ms. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputEvent {
+    pub key: String,
+    pub kind: KeyEventKind,
+    pub time: u128,
+}
+
+impl InputEvent {
+    pub fn press<S: Into<String>>(key: S, time: u128) -> Self {
+        InputEvent { key: key.into(), kind: KeyEventKind::Press, time }
+    }
+
+    pub fn release<S: Into<String>>(key: S, time: u128) -> Self {
+        InputEvent { key: key.into(), kind: KeyEventKind::Release, time }
+    }
+}
+
+pub trait EventSource {
+    fn next_event(&mut self) -> Option<InputEvent>;
+}
+
+/* Pre-recorded events, replayed in order. Text form, one event per line:
+ *   <time_ms> press|release <key>
+ * comments: lines starting with '#'; blank lines ignored. */
+#[derive(Debug, Clone, Default)]
+pub struct RecordedEvents {
+    events: std::collections::VecDeque<InputEvent>,
+}
+
+impl RecordedEvents {
+    pub fn new(events: Vec<InputEvent>) -> Self {
+        RecordedEvents { events: events.into() }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        text.lines()
+            .enumerate()
+            .map(|(idx, raw)| (idx + 1, raw.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(line_no, line)| {
+                let mut parts = line.split_whitespace();
+                let time = parts
+                    .next()
+                    .and_then(|t| t.parse::<u128>().ok())
+                    .ok_or(format!("line {line_no}: expected a timestamp in ms"))?;
+                let kind = match parts.next() {
+                    Some("press") => KeyEventKind::Press,
+                    Some("release") => KeyEventKind::Release,
+                    _ => return Err(format!("line {line_no}: expected 'press' or 'release'")),
+                };
+                let key = parts.next().ok_or(format!("line {line_no}: missing key token"))?;
+                Ok(InputEvent { key: key.to_string(), kind, time })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self::new)
+    }
+}
+
+impl EventSource for RecordedEvents {
+    fn next_event(&mut self) -> Option<InputEvent> {
+        self.events.pop_front()
+    }
+}
+
+/* One word of a batch token stream: `w` or `w@1200` (ms). An untimed
+ * token comes `gap` ms after the previous one, or at 0 if it is the first.
+ * Times may not go back. */
+pub fn parse_timed_token(word: &str, prev: Option<u128>, gap: u128) -> Result<InputEvent, String> {
+    let (key, time) = match word.rsplit_once('@') {
+        Some((key, ms)) if !key.is_empty() => {
+            let ms = ms.parse::<u128>().map_err(|_| format!("bad timestamp in `{word}`"))?;
+            (key, ms)
+        }
+        _ => (word, prev.map_or(0, |t| t + gap)),
+    };
+    match prev {
+        Some(prev) if time < prev => Err(format!("`{word}`: time goes back ({time}ms after {prev}ms)")),
+        _ => Ok(InputEvent::press(key, time)),
+    }
+}
+
 pub mod io_shell {
+    use std::cell::UnsafeCell;
     use std::io::{self, Read};
-    use std::process::{Command, Stdio};
+    use std::mem::MaybeUninit;
+    use std::os::fd::RawFd;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::Once;
     use std::time::{Duration, Instant};
 
-    pub fn enable_raw_mode() -> io::Result<()> {
-        let status = Command::new("sh")
-            .arg("-c")
-            .arg("stty -echo -icanon time 1 min 0")
-            .stdin(Stdio::inherit())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status()?;
-        if status.success() {
-            Ok(())
-        } else {
-            Err(io::Error::new(io::ErrorKind::Other, "stty failed"))
+    /* Raw mode on a terminal for as long as the guard lives, as
+     * `stty -echo -icanon time 1 min 0` set it: no echo and no line
+     * buffering, while ctrl-c, ctrl-z and ctrl-\ still raise their signals.
+     * Reads return after 100ms without input. Dropping the guard puts back
+     * the exact attributes it found. */
+    pub struct RawMode {
+        fd: RawFd,
+        saved: libc::termios,
+    }
+
+    /* What the signal handler and panic hook restore: the terminal raw
+     * mode is active on (-1 if none), and the attributes the latest
+     * `RawMode::enable` guard saved there. Read from a signal handler, so
+     * lock-free: `ACTIVE_SAVED` is only written while `ACTIVE_FD` is -1,
+     * and only read by whoever swaps a terminal out of `ACTIVE_FD`. */
+    static ACTIVE_FD: AtomicI32 = AtomicI32::new(-1);
+    static ACTIVE_SAVED: SavedAttrs = SavedAttrs(UnsafeCell::new(MaybeUninit::uninit()));
+
+    struct SavedAttrs(UnsafeCell<MaybeUninit<libc::termios>>);
+
+    /* shared only as `ACTIVE_FD` above allows */
+    unsafe impl Sync for SavedAttrs {}
+
+    fn publish(fd: RawFd, saved: &libc::termios) {
+        ACTIVE_FD.store(-1, Ordering::SeqCst);
+        unsafe { (*ACTIVE_SAVED.0.get()).write(*saved) };
+        ACTIVE_FD.store(fd, Ordering::SeqCst);
+    }
+
+    fn restore_active() {
+        let fd = ACTIVE_FD.swap(-1, Ordering::SeqCst);
+        if fd >= 0 {
+            /* tcsetattr is async-signal-safe */
+            unsafe { libc::tcsetattr(fd, libc::TCSANOW, (*ACTIVE_SAVED.0.get()).as_ptr()) };
+        }
+    }
+
+    extern "C" fn on_signal(sig: libc::c_int) {
+        restore_active();
+        /* die of the same signal, with its default action */
+        unsafe {
+            libc::signal(sig, libc::SIG_DFL);
+            libc::raise(sig);
+        }
+    }
+
+    /* Once per process: a panic hook, and handlers for the signals that
+     * end the process by default, both restoring the terminal first. */
+    fn install_restorers() {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            let previous = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                restore_active();
+                previous(info);
+            }));
+            for sig in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP, libc::SIGQUIT] {
+                unsafe {
+                    let mut action: libc::sigaction = std::mem::zeroed();
+                    action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                    libc::sigemptyset(&mut action.sa_mask);
+                    libc::sigaction(sig, &action, std::ptr::null_mut());
+                }
+            }
+        });
+    }
+
+    fn get_attrs(fd: RawFd) -> io::Result<libc::termios> {
+        let mut attrs: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut attrs) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(attrs)
+    }
+
+    fn set_attrs(fd: RawFd, attrs: &libc::termios) -> io::Result<()> {
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, attrs) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    impl RawMode {
+        /* Raw mode on stdin, also undone if the process panics or is
+         * killed by SIGTERM, SIGINT, SIGHUP or SIGQUIT. */
+        pub fn enable() -> io::Result<Self> {
+            let guard = Self::enable_on(libc::STDIN_FILENO)?;
+            install_restorers();
+            publish(guard.fd, &guard.saved);
+            Ok(guard)
+        }
+
+        /* Raw mode on `fd`, undone only when the guard is dropped. */
+        pub fn enable_on(fd: RawFd) -> io::Result<Self> {
+            let saved = get_attrs(fd)?;
+            let mut raw = saved;
+            raw.c_lflag &= !(libc::ECHO | libc::ICANON);
+            raw.c_cc[libc::VMIN] = 0;
+            raw.c_cc[libc::VTIME] = 1;
+            set_attrs(fd, &raw)?;
+            Ok(RawMode { fd, saved })
         }
     }
 
-    pub fn disable_raw_mode() {
-        let _ = Command::new("sh").arg("-c").arg("stty sane").status();
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            let _ = ACTIVE_FD.compare_exchange(self.fd, -1, Ordering::SeqCst, Ordering::SeqCst);
+            let _ = set_attrs(self.fd, &self.saved);
+        }
     }
 
     pub fn stdin_next_byte(timeout: Duration) -> Option<u8> {
@@ -180,6 +364,75 @@ pub mod io_shell {
         first_timeout: Duration,
         esc_tail_timeout: Duration,
     ) -> io::Result<Option<String>> {
-        Ok(super::decode_one_token_with(|t| stdin_next_byte(t), first_timeout, esc_tail_timeout))
+        Ok(super::decode_one_token_with(stdin_next_byte, first_timeout, esc_tail_timeout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_events_parse_in_order() {
+        let mut src = RecordedEvents::parse(
+            "# charge\n0 press left\n\n900 release left\n910 press q\n",
+        ).unwrap();
+        assert_eq!(src.next_event(), Some(InputEvent::press("left", 0)));
+        assert_eq!(src.next_event(), Some(InputEvent::release("left", 900)));
+        assert_eq!(src.next_event(), Some(InputEvent::press("q", 910)));
+        assert_eq!(src.next_event(), None);
+    }
+
+    #[test]
+    fn timed_tokens() {
+        assert_eq!(parse_timed_token("w", None, 60), Ok(InputEvent::press("w", 0)));
+        assert_eq!(parse_timed_token("w", Some(100), 60), Ok(InputEvent::press("w", 160)));
+        assert_eq!(parse_timed_token("down@1200", Some(100), 60), Ok(InputEvent::press("down", 1200)));
+        /* a bare `@` is a key like any other */
+        assert_eq!(parse_timed_token("@", Some(10), 60), Ok(InputEvent::press("@", 70)));
+        assert!(parse_timed_token("w@soon", None, 60).unwrap_err().contains("bad timestamp"));
+        assert!(parse_timed_token("w@50", Some(100), 60).unwrap_err().contains("time goes back"));
+    }
+
+    #[test]
+    fn raw_mode_guard_restores_the_exact_attributes() {
+        use io_shell::RawMode;
+
+        /* a pseudo-terminal stands in for the real one */
+        let (master, slave) = unsafe {
+            let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+            assert!(master >= 0 && libc::grantpt(master) == 0 && libc::unlockpt(master) == 0);
+            let name = libc::ptsname(master);
+            assert!(!name.is_null());
+            (master, libc::open(name, libc::O_RDWR | libc::O_NOCTTY))
+        };
+        assert!(slave >= 0);
+        let attrs = |fd| {
+            let mut t: libc::termios = unsafe { std::mem::zeroed() };
+            assert_eq!(unsafe { libc::tcgetattr(fd, &mut t) }, 0);
+            (t.c_iflag, t.c_oflag, t.c_cflag, t.c_lflag, t.c_cc)
+        };
+
+        let before = attrs(slave);
+        {
+            let _raw = RawMode::enable_on(slave).unwrap();
+            let (_, _, _, lflag, cc) = attrs(slave);
+            assert_eq!(lflag & (libc::ECHO | libc::ICANON), 0);
+            assert_eq!(lflag & libc::ISIG, before.3 & libc::ISIG);
+            assert_eq!((cc[libc::VMIN], cc[libc::VTIME]), (0, 1));
+        }
+        assert_eq!(attrs(slave), before);
+
+        assert!(RawMode::enable_on(-1).is_err());
+        unsafe {
+            libc::close(slave);
+            libc::close(master);
+        }
+    }
+
+    #[test]
+    fn recorded_events_reject_bad_lines() {
+        let err = RecordedEvents::parse("0 press q\n5 tap w\n").unwrap_err();
+        assert!(err.starts_with("line 2"));
     }
 }
@@ -0,0 +1,229 @@
+use crate::attrs::MoveFilter;
+use crate::engine::{is_late, MatchSource, MoveHit, StepOutcome};
+use crate::facing::to_relative;
+use crate::input::{InputEvent, KeyEventKind};
+use crate::policy::TimeoutPolicy;
+use crate::versus::{VersusConfig, VersusState};
+
+/* The `"v"` of every record. Fields may be added within a version; it goes
+ * up only when one is renamed, removed or changes meaning. The schema is
+ * documented in README.md. */
+pub const SCHEMA_VERSION: u32 = 1;
+
+/* `s` as a quoted JSON string. */
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_or_null<T, F: Fn(T) -> String>(value: Option<T>, f: F) -> String {
+    value.map_or_else(|| "null".to_string(), f)
+}
+
+/* {"name":"Fireball","character":null,"damage":12,"startup":null,"tags":["projectile"],"suffix":false} */
+pub fn move_json(m: &MoveHit) -> String {
+    let tags: Vec<String> = m.info.tags.iter().map(|t| json_string(t)).collect();
+    format!(
+        "{{\"name\":{},\"character\":{},\"damage\":{},\"startup\":{},\"tags\":[{}],\"suffix\":{}}}",
+        json_string(&m.name),
+        json_or_null(m.info.character.as_deref(), json_string),
+        json_or_null(m.info.damage, |d| d.to_string()),
+        json_or_null(m.info.startup, |s| s.to_string()),
+        tags.join(","),
+        m.source == MatchSource::Suffix,
+    )
+}
+
+/* One line of output; `key`, `kind` and `internal` are None for moves
+ * fired by a timer rather than a key. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventRecord<'a> {
+    pub time_ms: u128,
+    /* 0-based; written 1-based */
+    pub player: usize,
+    pub key: Option<&'a str>,
+    pub kind: Option<KeyEventKind>,
+    pub internal: Option<String>,
+    pub state_before: usize,
+    pub state_after: usize,
+    pub timeout_reset: bool,
+    pub moves: Vec<&'a MoveHit>,
+}
+
+impl EventRecord<'_> {
+    pub fn to_json(&self) -> String {
+        let kind = self.kind.map(|k| match k {
+            KeyEventKind::Press => "press",
+            KeyEventKind::Release => "release",
+        });
+        let moves: Vec<String> = self.moves.iter().map(|m| move_json(m)).collect();
+        format!(
+            "{{\"v\":{SCHEMA_VERSION},\"time_ms\":{},\"player\":{},\"key\":{},\"kind\":{},\"internal\":{},\
+             \"state_before\":{},\"state_after\":{},\"timeout_reset\":{},\"moves\":[{}]}}",
+            self.time_ms,
+            self.player + 1,
+            json_or_null(self.key, json_string),
+            json_or_null(kind, json_string),
+            json_or_null(self.internal.as_deref(), json_string),
+            self.state_before,
+            self.state_after,
+            self.timeout_reset,
+            moves.join(","),
+        )
+    }
+}
+
+/* One record per player `ev` was routed to, from the states around
+ * `step_versus_outcome` and what it returned. Moves the filter rejects
+ * are left out. A timeout reset is a bound key pressed too late for the
+ * combo in progress, unless `on_timeout` is `keep`. */
+pub fn key_records<'a>(
+    cfg: &VersusConfig,
+    before: &VersusState,
+    after: &VersusState,
+    ev: &'a InputEvent,
+    outcomes: &'a [(usize, StepOutcome)],
+    filter: &MoveFilter,
+) -> Vec<EventRecord<'a>> {
+    outcomes
+        .iter()
+        .map(|(player, outcome)| {
+            let (pcfg, pst) = (&cfg.players[*player], &before.players[*player]);
+            let internal = pcfg.key_to_internal.get(&ev.key).map(|s| to_relative(s, pst.facing));
+            let timeout_reset = ev.kind == KeyEventKind::Press
+                && internal.is_some()
+                && pst.cur_state != 0
+                && pcfg.on_timeout != TimeoutPolicy::Keep
+                && is_late(pcfg, pst, ev.time);
+            EventRecord {
+                time_ms: ev.time,
+                player: *player,
+                key: Some(&ev.key),
+                kind: Some(ev.kind),
+                internal,
+                state_before: pst.cur_state,
+                state_after: after.players[*player].cur_state,
+                timeout_reset,
+                moves: outcome.moves.iter().filter(|m| filter.matches(&m.info)).collect(),
+            }
+        })
+        .collect()
+}
+
+/* Records for moves a timer fired (`tick_versus`, `flush_versus`); players
+ * with no move to report get none. */
+pub fn timer_records<'a>(
+    before: &VersusState,
+    after: &VersusState,
+    time_ms: u128,
+    outcomes: &'a [(usize, StepOutcome)],
+    filter: &MoveFilter,
+) -> Vec<EventRecord<'a>> {
+    outcomes
+        .iter()
+        .map(|(player, outcome)| EventRecord {
+            time_ms,
+            player: *player,
+            key: None,
+            kind: None,
+            internal: None,
+            state_before: before.players[*player].cur_state,
+            state_after: after.players[*player].cur_state,
+            timeout_reset: false,
+            moves: outcome.moves.iter().filter(|m| filter.matches(&m.info)).collect(),
+        })
+        .filter(|r| !r.moves.is_empty())
+        .collect()
+}
+
+/* Records for a frontend command every player obeys (`ctrl-f` swaps
+ * sides, `ctrl-n` switches characters): one per player, bound to nothing
+ * and firing nothing. */
+pub fn command_records<'a>(before: &VersusState, after: &VersusState, key: &'a str, time_ms: u128)
+    -> Vec<EventRecord<'a>>
+{
+    (0..after.players.len())
+        .map(|player| EventRecord {
+            time_ms,
+            player,
+            key: Some(key),
+            kind: Some(KeyEventKind::Press),
+            internal: None,
+            state_before: before.players[player].cur_state,
+            state_after: after.players[player].cur_state,
+            timeout_reset: false,
+            moves: Vec::new(),
+        })
+        .collect()
+}
+
+/* Writes `records` to stdout, one per line; returns how many moves they
+ * hold. Every frontend writes JSON Lines through it. */
+pub fn emit(records: &[EventRecord]) -> usize {
+    for r in records {
+        println!("{}", r.to_json());
+    }
+    records.iter().map(|r| r.moves.len()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::EngineOptions;
+    use crate::versus::{flip_all, step_versus_outcome, tick_versus, versus_from_gmr_file};
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!(json_string("Claw \"Slam\""), "\"Claw \\\"Slam\\\"\"");
+        assert_eq!(json_string("a\\b\nc\u{1}é"), "\"a\\\\b\\nc\\u0001é\"");
+    }
+
+    #[test]
+    fn key_presses_become_records() {
+        let (cfg, st) = versus_from_gmr_file("grammar/test.gmr", &EngineOptions::default()).unwrap();
+        let all = MoveFilter::default();
+        let mut lines = Vec::new();
+        let keys = [("down", 0), ("right", 100), ("w", 200), ("x", 300), ("down", 400), ("right", 1000)];
+        let st = keys.iter().fold(st, |st, (key, time)| {
+            let ev = InputEvent::press(*key, *time);
+            let (after, outcomes) = step_versus_outcome(&cfg, st.clone(), &ev);
+            lines.extend(key_records(&cfg, &st, &after, &ev, &outcomes, &all).iter().map(EventRecord::to_json));
+            after
+        });
+        assert_eq!(lines.len(), 6);
+        assert!(lines[0].starts_with(
+            "{\"v\":1,\"time_ms\":0,\"player\":1,\"key\":\"down\",\"kind\":\"press\",\"internal\":\"[Down]\",\"state_before\":0,"
+        ), "{}", lines[0]);
+        assert!(lines[2].ends_with(
+            "\"timeout_reset\":false,\"moves\":[{\"name\":\"Fireball (Generic)\",\"character\":null,\"damage\":null,\
+             \"startup\":null,\"tags\":[],\"suffix\":false}]}"
+        ), "{}", lines[2]);
+        assert!(lines[3].contains("\"key\":\"x\",\"kind\":\"press\",\"internal\":null,"));
+        assert!(lines[3].contains("\"state_after\":0,\"timeout_reset\":false,\"moves\":[]"));
+        assert!(lines[4].contains("\"timeout_reset\":false"), "{}", lines[4]);
+        /* 600ms after [Down]: the Fireball starts over */
+        assert!(lines[5].contains("\"timeout_reset\":true"), "{}", lines[5]);
+
+        let (after, outcomes) = tick_versus(&cfg, st.clone(), 5000);
+        assert_eq!(timer_records(&st, &after, 5000, &outcomes, &all), vec![]);
+
+        let flipped = flip_all(&cfg, st.clone());
+        let lines: Vec<String> = command_records(&st, &flipped, "ctrl-f", 5100).iter().map(EventRecord::to_json).collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains("\"key\":\"ctrl-f\",\"kind\":\"press\",\"internal\":null,"), "{}", lines[0]);
+        assert!(lines[0].ends_with("\"moves\":[]}"), "{}", lines[0]);
+    }
+}
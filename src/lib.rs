@@ -1,8 +1,21 @@
 pub mod parse;
+pub mod pattern;
+pub mod attrs;
+pub mod diag;
+pub mod lint;
 pub mod automaton;
+pub mod dfa;
+pub mod dot;
 pub mod input;
+pub mod clock;
+pub mod facing;
+pub mod policy;
 
 pub mod engine;
+pub mod versus;
+pub mod replay;
+pub mod gmrtest;
+pub mod jsonl;
 
 pub mod apps {
     pub mod cli;
@@ -0,0 +1,241 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::automaton::Automaton;
+use crate::diag::{Diagnostic, Severity};
+use crate::facing::{map_step, to_relative, Facing};
+use crate::parse::{chord_members, split_step, CompiledGrammar, Rule, Token};
+use crate::policy::NOISE;
+
+/* Plain internal tokens a step needs bound: "[A]+[B]" -> [A], [B];
+ * "^[A]" and "[A]~800" -> [A]. Directions are compared as forward/back,
+ * so binding [Right] covers [Fwd]. */
+fn base_tokens(tok: &str) -> Vec<String> {
+    chord_members(tok).into_iter().map(|m| relative(split_step(m).1)).collect()
+}
+
+fn relative(internal: &str) -> String {
+    to_relative(internal, Facing::Right)
+}
+
+fn steps(r: &Rule) -> Vec<&str> {
+    r.sequence.iter().map(|t| t.as_str()).collect()
+}
+
+/* [?] counts as bound when the grammar turns unbound keys into noise. */
+fn unbound_tokens(g: &CompiledGrammar) -> Vec<Diagnostic> {
+    let noise = g.settings.iter().any(|s| s.name == "on_unknown_key" && s.value == "noise");
+    let bound: BTreeSet<String> = g
+        .bindings
+        .iter()
+        .map(|b| relative(&b.internal))
+        .chain(noise.then(|| NOISE.to_string()))
+        .collect();
+    g.combos
+        .iter()
+        .flat_map(|r| {
+            let missing: BTreeSet<String> = r
+                .sequence
+                .iter()
+                .flat_map(|t| base_tokens(t.as_str()))
+                .filter(|t| !bound.contains(t))
+                .collect();
+            missing.into_iter().map(move |t| {
+                let why = if t == NOISE {
+                    format!("{NOISE} needs `set on_unknown_key = noise`")
+                } else {
+                    format!("no key is bound to {t}")
+                };
+                Diagnostic::new(Severity::Error, r.loc.clone(), format!("'{}' can never fire: {why}", r.move_name))
+            })
+        })
+        .collect()
+}
+
+fn unused_bindings(g: &CompiledGrammar) -> Vec<Diagnostic> {
+    let used: BTreeSet<String> = g
+        .combos
+        .iter()
+        .flat_map(|r| r.sequence.iter().flat_map(|t| base_tokens(t.as_str())))
+        .collect();
+    g.bindings
+        .iter()
+        .filter(|b| !used.contains(&relative(&b.internal)))
+        .map(|b| {
+            Diagnostic::new(
+                Severity::Warning,
+                b.loc.clone(),
+                format!("binding '{}' is unused: no combo uses {}", b.key, b.internal),
+            )
+        })
+        .collect()
+}
+
+/* Combos of different characters never run in the same moveset. */
+fn same_moveset(a: &Rule, b: &Rule) -> bool {
+    a.character.is_none() || b.character.is_none() || a.character == b.character
+}
+
+fn duplicate_combos(g: &CompiledGrammar) -> Vec<Diagnostic> {
+    let mut seen: BTreeMap<Vec<&str>, Vec<&Rule>> = BTreeMap::new();
+    g.combos
+        .iter()
+        .filter_map(|r| {
+            let earlier = seen.entry(steps(r)).or_default();
+            let first = earlier.iter().find(|first| same_moveset(first, r)).copied();
+            if first.is_none() {
+                earlier.push(r);
+            }
+            first.map(|first| (first, r))
+        })
+        .filter(|(first, r)| first.loc != r.loc)
+        .map(|(first, r)| {
+            Diagnostic::new(
+                Severity::Warning,
+                r.loc.clone(),
+                format!(
+                    "'{}' has the same sequence as '{}' (line {}); the resolution policy decides which of them fires",
+                    r.move_name, first.move_name, first.loc.line,
+                ),
+            )
+        })
+        .collect()
+}
+
+/* B is a strict suffix of A when B's trie node lies on the failure chain
+ * of A's: reaching A then always reports B as well. */
+fn suffix_combos(g: &CompiledGrammar, a: &Automaton) -> Vec<Diagnostic> {
+    let ends: Vec<(usize, &Rule)> = g
+        .combos
+        .iter()
+        .filter_map(|r| a.trie_state(&steps(r)).map(|s| (s, r)))
+        .collect();
+    let by_state: BTreeMap<usize, Vec<&Rule>> = ends.iter().fold(BTreeMap::new(), |mut m, &(s, r)| {
+        m.entry(s).or_default().push(r);
+        m
+    });
+
+    ends.iter()
+        .flat_map(|&(end, longer)| {
+            let chain = std::iter::successors(Some(a.state_info(end).1), |&f| {
+                (f != a.start()).then(|| a.state_info(f).1)
+            })
+            .take_while(|&f| f != a.start());
+            chain
+                .flat_map(|f| by_state.get(&f).into_iter().flatten())
+                /* `[Down]?, [FP]` legitimately expands to a suffix of itself */
+                .filter(|shorter| shorter.loc != longer.loc && same_moveset(shorter, longer))
+                .map(|shorter| {
+                    Diagnostic::new(
+                        Severity::Warning,
+                        longer.loc.clone(),
+                        format!(
+                            "'{}' also fires '{}' (line {}), whose sequence is a suffix of this one",
+                            longer.move_name, shorter.move_name, shorter.loc.line,
+                        ),
+                    )
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/* Semantic checks on a grammar that parsed fine, in source order. Combos
+ * are compared as the engine sees them, so "[Right]" and "[Fwd]" are the
+ * same step. */
+pub fn lint(g: &CompiledGrammar) -> Vec<Diagnostic> {
+    let step = |t: &Token| Token::new(map_step(t.as_str(), &|m| relative(m)));
+    let combos = g.combos.iter().map(|r| Rule { sequence: r.sequence.iter().map(step).collect(), ..r.clone() });
+    let g = &CompiledGrammar { combos: combos.collect(), ..g.clone() };
+    let a = &Automaton::from_combos(&g.combos);
+    let mut diags: Vec<Diagnostic> = unbound_tokens(g)
+        .into_iter()
+        .chain(unused_bindings(g))
+        .chain(duplicate_combos(g))
+        .chain(suffix_combos(g, a))
+        .collect();
+    diags.sort_by(|x, y| (&x.loc.file, x.loc.line).cmp(&(&y.loc.file, y.loc.line)));
+    diags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{classify, parse_gmr, parse_gmr_file};
+
+    fn lint_str(src: &str) -> Vec<(usize, Severity)> {
+        let g = classify(&parse_gmr(src).unwrap());
+        lint(&g).iter().map(|d| (d.loc.line, d.severity)).collect()
+    }
+
+    #[test]
+    fn finds_each_kind_of_problem() {
+        let found = lint_str("
+            [A], [B] -> Long
+            [B] -> Short
+            [A], [C]+[B] -> Chord
+            [A], [B] -> Long Again
+            a -> [A]
+            b -> [B]
+            z -> [Z]
+        ");
+        assert_eq!(found, vec![
+            (2, Severity::Warning),
+            (4, Severity::Error),
+            (5, Severity::Warning),
+            (5, Severity::Warning),
+            (8, Severity::Warning),
+        ]);
+    }
+
+    #[test]
+    fn characters_do_not_clash_with_each_other() {
+        let found = lint_str("
+            a -> [A]
+            b -> [B]
+            [B] -> Shared
+            [character \"Jax\"]
+            [A], [B] -> Jax Move
+            [character \"Kano\"]
+            [A], [B] -> Kano Move
+            [B], [B] -> Kano Twice
+            [B], [B] -> Kano Again
+        ");
+        /* both character moves end with the shared [B]; only Kano repeats itself */
+        assert_eq!(found, vec![
+            (6, Severity::Warning),
+            (8, Severity::Warning),
+            (9, Severity::Warning),
+            (10, Severity::Warning),
+            (10, Severity::Warning),
+        ]);
+    }
+
+    #[test]
+    fn noise_steps_need_the_noise_policy() {
+        let src = "q -> [BP]\n[BP], [?] -> Feint";
+        let g = classify(&parse_gmr(src).unwrap());
+        let found: Vec<String> = lint(&g).into_iter().map(|d| d.message).collect();
+        assert_eq!(found, vec!["'Feint' can never fire: [?] needs `set on_unknown_key = noise`".to_string()]);
+        assert!(lint_str(&format!("set on_unknown_key = noise\n{src}")).is_empty());
+    }
+
+    #[test]
+    fn directions_compare_as_forward_and_back() {
+        let found = lint_str("
+            right -> [Right]
+            w -> [FP]
+            [Right], [FP] -> Absolute
+            [Fwd], [FP] -> Relative
+        ");
+        assert_eq!(found, vec![(5, Severity::Warning)]);
+    }
+
+    #[test]
+    fn test_grammar_flags_slide_duplicate() {
+        let g = classify(&parse_gmr_file("grammar/test.gmr").unwrap());
+        let diags = lint(&g);
+        assert!(diags.iter().any(|d| d.loc.line == 11 && d.message.contains("Slide (Generic)")));
+        assert!(diags.iter().any(|d| d.message.contains("binding 'o' is unused")));
+        assert!(diags.iter().all(|d| d.severity == Severity::Warning), "{diags:?}");
+    }
+}
@@ -1,10 +1,19 @@
-use std::collections::{BTreeSet};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::attrs::{parse_attrs, split_attrs, Attr, MoveInfo, RuleAttrs};
+use crate::diag::{Diagnostic, Severity, SourceLoc};
+use crate::pattern::{expand, macro_refs, parse_pattern, resolve_macros, MacroError, Pattern};
+use crate::policy::{ResolutionPolicy, TimeoutPolicy, UnknownKeyPolicy};
 
 #[derive(Debug, Clone)]
 pub struct Binding {
     pub key: String,
     pub internal: String,
+    pub loc: SourceLoc,
+    /* None = shared by every player */
+    pub player: Option<usize>,
 }
 
 #[derive(Debug, Clone)]
@@ -13,6 +22,54 @@ pub struct CompiledGrammar {
     pub bindings: Vec<Binding>,
     pub internal_alphabet: Vec<String>,
     pub key_alphabet: Vec<String>,
+    /* rules that are neither a binding nor a combo */
+    pub discarded: Vec<Discarded>,
+    pub settings: Vec<Setting>,
+}
+
+/* Why `classify` could not use a rule. Only `key -> [Internal]` bindings
+ * and `[A], [B], ... -> Move` combos are meaningful. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DiscardReason {
+    /* `BP, FP -> Saibot Blast`: a move whose steps are not bracketed */
+    UnbracketedCombo,
+    /* `[BK], FP -> Move`: bracketed and bare steps mixed */
+    MixedSequence,
+    /* `[BP] -> [FP]`: internal token mapped to internal token */
+    InternalToInternal,
+    /* `a, b -> [X]`: a binding must bind exactly one key */
+    MultiKeyBinding,
+}
+
+impl DiscardReason {
+    pub fn message(&self) -> &'static str {
+        match self {
+            DiscardReason::UnbracketedCombo =>
+                "combo steps must be bracketed internal tokens, e.g. [BP]",
+            DiscardReason::MixedSequence =>
+                "sequence mixes bracketed internal tokens and bare keys",
+            DiscardReason::InternalToInternal =>
+                "internal token mapped to another internal token",
+            DiscardReason::MultiKeyBinding =>
+                "a binding maps exactly one key to an internal token",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Discarded {
+    pub rule: Rule,
+    pub reason: DiscardReason,
+}
+
+impl Discarded {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::new(
+            Severity::Warning,
+            self.rule.loc.clone(),
+            format!("rule ignored: {}", self.reason.message()),
+        )
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -21,21 +78,162 @@ pub struct Token(String);
 impl Token {
     pub fn new<S: Into<String>>(s: S) -> Self {
         let t = s.into().trim().to_string();
-        Self(t)
+        let members = chord_members(&t);
+        match split_step(&t) {
+            _ if members.len() > 1 => Self(chord_token(&members)),
+            (StepKind::Release, base) => Self(release_token(base)),
+            (StepKind::Hold(ms), base) => Self(hold_token(base, ms)),
+            (StepKind::Press, _) => Self(t),
+        }
     }
     pub fn as_str(&self) -> &str { &self.0 }
+    pub fn is_chord(&self) -> bool { chord_members(&self.0).len() > 1 }
+    pub fn step_kind(&self) -> StepKind { split_step(&self.0).0 }
+}
+
+/* What a sequence step waits for: a press of the token, its release
+ * ("^[BP]"), or a press held for at least N ms before release ("[Back]~800"). */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum StepKind {
+    Press,
+    Release,
+    Hold(u64),
+}
+
+/* "^[BP]" -> (Release, "[BP]"), "[Back]~800" -> (Hold(800), "[Back]"), else (Press, tok). */
+pub fn split_step(tok: &str) -> (StepKind, &str) {
+    if let Some(base) = tok.strip_prefix('^').map(str::trim) {
+        if is_bracketed(base) {
+            return (StepKind::Release, base);
+        }
+    }
+    if let Some((base, ms)) = tok.rsplit_once('~') {
+        let base = base.trim();
+        if let (true, Ok(ms)) = (is_bracketed(base), ms.trim().parse::<u64>()) {
+            return (StepKind::Hold(ms), base);
+        }
+    }
+    (StepKind::Press, tok)
+}
+
+pub fn release_token(internal: &str) -> String { format!("^{internal}") }
+pub fn hold_token(internal: &str, ms: u64) -> String { format!("{internal}~{ms}") }
+
+fn is_bracketed(s: &str) -> bool { s.len() >= 2 && s.starts_with('[') && s.ends_with(']') }
+
+/* "[BP]+[FP]" -> ["[BP]", "[FP]"]; anything that is not a chord comes back as itself. */
+pub fn chord_members(tok: &str) -> Vec<&str> {
+    let parts: Vec<&str> = tok.split('+').map(str::trim).collect();
+    if parts.len() > 1 && parts.iter().all(|p| is_bracketed(p)) {
+        parts
+    } else {
+        vec![tok]
+    }
+}
+
+/* Canonical chord symbol: members sorted and deduplicated, so [FP]+[BP] == [BP]+[FP]. */
+pub fn chord_token<S: AsRef<str>>(members: &[S]) -> String {
+    members
+        .iter()
+        .map(|m| m.as_ref().trim())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>()
+        .join("+")
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Rule {
     pub sequence: Vec<Token>,
     pub move_name: String,
+    pub loc: SourceLoc,
+    /* the sequence as written; rules expanded from one line share it */
+    pub pattern: Vec<Pattern>,
+    /* `[character "Jax"]` section the rule appeared in; None = every character */
+    pub character: Option<String>,
+    /* `[player N]` section the rule appeared in; None = every player */
+    pub player: Option<usize>,
+    /* `{max_gap=150, ...}` after the move name */
+    pub attrs: RuleAttrs,
+}
+
+/* `set timeout_ms = 400`; inside a `[player N]` section it applies to that
+ * player only. Names and values are checked by `check_setting`. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Setting {
+    pub name: String,
+    pub value: String,
+    pub player: Option<usize>,
+    pub loc: SourceLoc,
+}
+
+/* Attribute names a rule may carry after its move name. */
+pub const RULE_ATTRS: &[&str] =
+    &["max_gap", "min_gap", "max_total", "priority", "character", "damage", "startup", "tags"];
+
+/* A rule's attribute block, checked; errors carry the offending
+ * attribute's offset. */
+pub fn rule_attrs(attrs: &[Attr]) -> Result<RuleAttrs, (String, usize)> {
+    let mut out = RuleAttrs::default();
+    for a in attrs {
+        let ms = || a.value.parse::<u64>().map_err(|_| (format!("{} expects milliseconds, found `{}`", a.key, a.value), a.at));
+        let number = || a.value.parse::<u32>().map_err(|_| (format!("{} expects a number, found `{}`", a.key, a.value), a.at));
+        match a.key.as_str() {
+            "max_gap" => out.timing.max_gap = Some(ms()?),
+            "min_gap" => out.timing.min_gap = Some(ms()?),
+            "max_total" => out.timing.max_total = Some(ms()?),
+            "priority" => {
+                out.priority = a.value.parse().map_err(|_| (format!("priority expects an integer, found `{}`", a.value), a.at))?
+            }
+            "character" => out.info.character = Some(a.value.clone()),
+            "damage" => out.info.damage = Some(number()?),
+            "startup" => out.info.startup = Some(number()?),
+            "tags" => out.info.tags = a.value.split(',').map(str::trim).filter(|t| !t.is_empty()).map(String::from).collect(),
+            key => return Err((format!("unknown attribute `{key}` (expected one of: {})", RULE_ATTRS.join(", ")), a.at)),
+        }
+    }
+    if let (Some(min), Some(max)) = (out.timing.min_gap, out.timing.max_gap) {
+        if min > max {
+            let at = attrs.iter().find(|a| a.key == "min_gap").map_or(0, |a| a.at);
+            return Err((format!("min_gap ({min}) is larger than max_gap ({max})"), at));
+        }
+    }
+    Ok(out)
+}
+
+/* Setting names a grammar may use. */
+pub const SETTINGS: &[&str] = &["timeout_ms", "on_timeout", "on_unknown_key", "resolution"];
+
+pub fn check_setting(name: &str, value: &str) -> Result<(), String> {
+    match name {
+        "timeout_ms" => value
+            .parse::<u64>()
+            .map(|_| ())
+            .map_err(|_| format!("timeout_ms expects milliseconds, found `{value}`")),
+        "on_timeout" => value.parse::<TimeoutPolicy>().map(|_| ()),
+        "on_unknown_key" => value.parse::<UnknownKeyPolicy>().map(|_| ()),
+        "resolution" => value.parse::<ResolutionPolicy>().map(|_| ()),
+        _ => Err(format!("unknown setting `{name}` (expected one of: {})", SETTINGS.join(", "))),
+    }
+}
+
+impl CompiledGrammar {
+    /* Highest `[player N]` section used, at least 1. */
+    pub fn players(&self) -> usize {
+        self.bindings
+            .iter()
+            .filter_map(|b| b.player)
+            .chain(self.settings.iter().filter_map(|s| s.player))
+            .max()
+            .unwrap_or(1)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Grammar {
     pub rules: Vec<Rule>,
     pub alphabet: Vec<Token>,
+    pub settings: Vec<Setting>,
 }
 
 #[derive(Debug)]
@@ -44,69 +242,486 @@ pub enum ParseError {
     EmptySequence { line_no: usize },
     MissingArrow { line_no: usize },
     EmptyMoveName { line_no: usize },
+    InvalidSequence { line_no: usize, reason: String },
+    InvalidMacro { line_no: usize, reason: String },
+    UndefinedMacro { line_no: usize, name: String },
+    /* the cycle, e.g. ["A", "B", "A"] */
+    RecursiveMacro { line_no: usize, cycle: Vec<String> },
+    Include { line_no: usize, reason: String },
+    /* files on the cycle, starting and ending with the same one */
+    IncludeCycle { line_no: usize, chain: Vec<String> },
+    InvalidSection { line_no: usize, reason: String },
+    InvalidSetting { line_no: usize, reason: String },
+    InvalidAttribute { line_no: usize, reason: String },
+}
+
+impl ParseError {
+    /* The message without the "line N:" prefix, as used in diagnostics. */
+    pub fn message(&self) -> String {
+        match self {
+            ParseError::Io(e) => format!("I/O error: {e}"),
+            ParseError::EmptySequence { .. } => "empty sequence before '->'".to_string(),
+            ParseError::MissingArrow { .. } => "expected '->' in rule".to_string(),
+            ParseError::EmptyMoveName { .. } => "empty move name after '->'".to_string(),
+            ParseError::InvalidSequence { reason, .. } => format!("invalid sequence: {reason}"),
+            ParseError::InvalidMacro { reason, .. } => format!("invalid macro: {reason}"),
+            ParseError::UndefinedMacro { name, .. } => format!("undefined macro `${name}`"),
+            ParseError::RecursiveMacro { cycle, .. } => format!(
+                "recursive macro: {}",
+                cycle.iter().map(|n| format!("${n}")).collect::<Vec<_>>().join(" -> ")
+            ),
+            ParseError::Include { reason, .. } => format!("invalid include: {reason}"),
+            ParseError::IncludeCycle { chain, .. } => format!("include cycle: {}", chain.join(" -> ")),
+            ParseError::InvalidSection { reason, .. } => format!("invalid section: {reason}"),
+            ParseError::InvalidSetting { reason, .. } => format!("invalid setting: {reason}"),
+            ParseError::InvalidAttribute { reason, .. } => format!("invalid attribute: {reason}"),
+        }
+    }
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ParseError::Io(e) => write!(f, "I/O error: {e}"),
-            ParseError::EmptySequence { line_no } =>
-                write!(f, "line {line_no}: empty sequence before '->'"),
-            ParseError::MissingArrow { line_no } =>
-                write!(f, "line {line_no}: expected '->' in rule"),
-            ParseError::EmptyMoveName { line_no } =>
-                write!(f, "line {line_no}: empty move name after '->'"),
+            ParseError::EmptySequence { line_no }
+            | ParseError::MissingArrow { line_no }
+            | ParseError::EmptyMoveName { line_no }
+            | ParseError::InvalidSequence { line_no, .. }
+            | ParseError::InvalidMacro { line_no, .. }
+            | ParseError::UndefinedMacro { line_no, .. }
+            | ParseError::RecursiveMacro { line_no, .. }
+            | ParseError::Include { line_no, .. }
+            | ParseError::IncludeCycle { line_no, .. }
+            | ParseError::InvalidSection { line_no, .. }
+            | ParseError::InvalidSetting { line_no, .. }
+            | ParseError::InvalidAttribute { line_no, .. } =>
+                write!(f, "line {line_no}: {}", self.message()),
         }
     }
 }
 
 impl std::error::Error for ParseError {}
 
-/*
- * line := <sequence> "->" <move_name>
- * sequence := token ("," token)*
- * token := non-empty string without comma/newline (trimmed)
- * comments: lines starting with '#' (ignored)
- * blank lines ignored
- */
-pub fn parse_gmr(input: &str) -> Result<Grammar, ParseError> {
-    let rules: Vec<Rule> = input
+/* A line may expand into several rules (repetition, optional steps,
+ * alternation); they share the move name, location and pattern. */
+fn parse_rule(line: &SourceLine, macros: &Macros) -> Result<Vec<Rule>, (ParseError, SourceLoc)> {
+    let (file, line_no, raw_line) = (line.file.as_deref(), line.line_no, line.text.as_str());
+    let whole = || SourceLoc::whole_line(file, line_no, raw_line);
+
+    let arrow = raw_line
+        .find("->")
+        .ok_or((ParseError::MissingArrow { line_no }, whole()))?;
+    let (lhs, rhs) = (raw_line[..arrow].trim(), raw_line[arrow + 2..].trim());
+    let rhs_start = raw_line.len() - raw_line[arrow + 2..].trim_start().len();
+    let (rhs, attrs) = split_attrs(rhs);
+
+    if rhs.is_empty() {
+        let loc = SourceLoc::new(file, line_no, raw_line, arrow..arrow + 2);
+        return Err((ParseError::EmptyMoveName { line_no }, loc));
+    }
+
+    let attrs = match attrs {
+        None => RuleAttrs::default(),
+        Some((block, block_start)) => parse_attrs(block).and_then(|attrs| rule_attrs(&attrs)).map_err(|(reason, at)| {
+            let at = rhs_start + block_start + at;
+            let loc = SourceLoc::new(file, line_no, raw_line, at..(raw_line.trim_end().len() - 1).max(at + 1));
+            (ParseError::InvalidAttribute { line_no, reason }, loc)
+        })?,
+    };
+    /* a section's character stands in for an unset `character=` */
+    let attrs = RuleAttrs {
+        info: MoveInfo { character: attrs.info.character.clone().or_else(|| line.section.character.clone()), ..attrs.info },
+        ..attrs
+    };
+
+    let lhs_start = raw_line.len() - raw_line.trim_start().len();
+    let invalid = |reason: String, at: usize| {
+        let at = lhs_start + at;
+        let loc = SourceLoc::new(file, line_no, raw_line, at..char_span(raw_line, at).end.min(arrow).max(at));
+        (ParseError::InvalidSequence { line_no, reason }, loc)
+    };
+
+    /* a lone bare key such as "(" or "|" is a binding, not a pattern */
+    let pattern = match parse_pattern(lhs) {
+        Ok(p) => p,
+        Err(_) if !lhs.contains(',') && !lhs.contains('[') => vec![Pattern::Step(Token::new(lhs))],
+        Err((reason, at)) => return Err(invalid(reason, at)),
+    };
+    let pattern = resolve_macros(&pattern, &macros.defs, &mut Vec::new())
+        .map_err(|e| macro_error(file, line_no, raw_line, &pattern, e, macros))?;
+
+    let sequences: Vec<Vec<Token>> = expand(&pattern)
+        .map_err(|reason| invalid(reason, 0))?
+        .into_iter()
+        .filter(|seq| !seq.is_empty())
+        .collect();
+
+    if sequences.is_empty() {
+        let loc = SourceLoc::new(file, line_no, raw_line, lhs_start.min(arrow)..arrow + 2);
+        return Err((ParseError::EmptySequence { line_no }, loc));
+    }
+
+    Ok(sequences
+        .into_iter()
+        .map(|sequence| Rule {
+            sequence,
+            move_name: rhs.to_string(),
+            loc: whole(),
+            pattern: pattern.clone(),
+            character: line.section.character.clone(),
+            player: line.section.player,
+            attrs: attrs.clone(),
+        })
+        .collect())
+}
+
+fn rule_lines(input: &str) -> impl Iterator<Item = (usize, &str)> {
+    input
         .lines()
         .enumerate()
-        .filter_map(|(idx, raw_line)| {
-            let line_no = idx + 1;
+        .map(|(idx, raw_line)| (idx + 1, raw_line))
+        .filter(|(_, raw_line)| {
             let line = raw_line.trim();
-            if line.is_empty() || line.starts_with('#') {
-                None
-            } else {
-                Some((line_no, line))
-            }
+            !(line.is_empty() || line.starts_with('#'))
         })
-        .map(|(line_no, line)| {
-            let (lhs, rhs) = line
-                .split_once("->")
-                .map(|(l, r)| (l.trim(), r.trim()))
-                .ok_or(ParseError::MissingArrow { line_no })?;
-
-            if rhs.is_empty() {
-                return Err(ParseError::EmptyMoveName { line_no });
+}
+
+/* Macro definitions, collected before any rule is parsed so a name may be
+ * used above the line that defines it. `broken` holds names whose
+ * definition did not parse, to avoid also calling them undefined. */
+#[derive(Debug, Default)]
+struct Macros {
+    defs: BTreeMap<String, Vec<Pattern>>,
+    broken: BTreeSet<String>,
+}
+
+/* "let QCF = [Down], [Right]" -> " QCF = [Down], [Right]" */
+fn let_body(raw_line: &str) -> Option<&str> {
+    raw_line.trim_start().strip_prefix("let").filter(|rest| rest.starts_with(char::is_whitespace))
+}
+
+fn is_macro_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/* Byte range of the first `$name` reference in `raw_line`. */
+fn macro_ref_span(raw_line: &str, name: &str) -> Option<std::ops::Range<usize>> {
+    let needle = format!("${name}");
+    raw_line.match_indices(&needle).map(|(at, _)| at..at + needle.len()).find(|span| {
+        !raw_line[span.end..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/* Byte range of the character at `at` in `text`; empty at the end. */
+fn char_span(text: &str, at: usize) -> std::ops::Range<usize> {
+    at..at + text[at..].chars().next().map_or(0, char::len_utf8)
+}
+
+/* Reports a failed macro resolution at the reference that caused it. */
+fn macro_error(
+    file: Option<&str>,
+    line_no: usize,
+    raw_line: &str,
+    pattern: &[Pattern],
+    err: MacroError,
+    macros: &Macros,
+) -> (ParseError, SourceLoc) {
+    let culprit = macro_refs(pattern)
+        .into_iter()
+        .find(|name| resolve_macros(&[Pattern::Step(Token::new(format!("${name}")))], &macros.defs, &mut Vec::new()).is_err());
+    let loc = culprit
+        .and_then(|name| macro_ref_span(raw_line, name))
+        .map_or_else(
+            || SourceLoc::whole_line(file, line_no, raw_line),
+            |span| SourceLoc::new(file, line_no, raw_line, span),
+        );
+    let e = match err {
+        MacroError::Undefined(name) if macros.broken.contains(&name) => {
+            ParseError::InvalidMacro { line_no, reason: format!("`${name}` is defined with errors") }
+        }
+        MacroError::Undefined(name) => ParseError::UndefinedMacro { line_no, name },
+        MacroError::Recursive(cycle) => ParseError::RecursiveMacro { line_no, cycle },
+    };
+    (e, loc)
+}
+
+/*
+ * First pass over the file: every `let NAME = <sequence>` line. Bodies are
+ * kept unresolved; a definition that is part of a cycle or uses an unknown
+ * name is reported here, at its own line, and again wherever it is used.
+ */
+fn collect_macros(lines: &[SourceLine]) -> (Macros, Vec<(ParseError, SourceLoc)>) {
+    let mut macros = Macros::default();
+    let mut errors = Vec::new();
+    let mut defined: Vec<(String, &SourceLine)> = Vec::new();
+
+    for line in lines {
+        let (file, line_no, raw_line) = (line.file.as_deref(), line.line_no, line.text.as_str());
+        let Some(body) = let_body(raw_line) else { continue };
+        let body_start = raw_line.len() - body.len();
+        let invalid = |reason: String, span: std::ops::Range<usize>| {
+            (ParseError::InvalidMacro { line_no, reason }, SourceLoc::new(file, line_no, raw_line, span))
+        };
+
+        let Some(eq) = body.find('=') else {
+            errors.push(invalid("expected '=' after the macro name".to_string(), body_start..raw_line.len()));
+            continue;
+        };
+        let name = body[..eq].trim();
+        let name_start = body_start + (eq - body[..eq].trim_start().len());
+        let name_span = name_start..name_start + name.len();
+        if !is_macro_name(name) {
+            errors.push(invalid(format!("`{name}` is not a valid macro name"), name_span));
+            continue;
+        }
+        if let Some((_, first)) = defined.iter().find(|(n, _)| n == name) {
+            let at = first.file.as_deref().map_or(format!("line {}", first.line_no), |f| format!("{f}:{}", first.line_no));
+            errors.push(invalid(format!("`${name}` is already defined at {at}"), name_span));
+            continue;
+        }
+        defined.push((name.to_string(), line));
+
+        let rhs = &body[eq + 1..];
+        let rhs_start = body_start + eq + 1 + (rhs.len() - rhs.trim_start().len());
+        match parse_pattern(rhs.trim()) {
+            Ok(items) if !items.is_empty() => {
+                macros.defs.insert(name.to_string(), items);
             }
+            Ok(_) => {
+                macros.broken.insert(name.to_string());
+                errors.push(invalid(format!("`${name}` has an empty body"), name_span));
+            }
+            Err((reason, at)) => {
+                macros.broken.insert(name.to_string());
+                let at = rhs_start + at;
+                errors.push(invalid(reason, char_span(raw_line, at)));
+            }
+        }
+    }
+
+    for (name, line) in &defined {
+        let (file, line_no, raw_line) = (line.file.as_deref(), &line.line_no, line.text.as_str());
+        let Some(body) = macros.defs.get(name) else { continue };
+        match resolve_macros(body, &macros.defs, &mut vec![name.clone()]) {
+            Ok(_) => {}
+            Err(MacroError::Recursive(cycle)) if cycle[0] == *name => {
+                let span = raw_line.find(name.as_str()).map_or(0..raw_line.len(), |at| at..at + name.len());
+                let loc = SourceLoc::new(file, *line_no, raw_line, span);
+                errors.push((ParseError::RecursiveMacro { line_no: *line_no, cycle }, loc));
+            }
+            Err(e) => errors.push(macro_error(file, *line_no, raw_line, body, e, &macros)),
+        }
+    }
+
+    (macros, errors)
+}
+
+/* One non-blank, non-comment line and the file it was read from. */
+#[derive(Debug, Clone)]
+struct SourceLine {
+    file: Option<String>,
+    line_no: usize,
+    text: String,
+    section: Section,
+}
+
+/* Headers in effect for a line. */
+#[derive(Debug, Clone, Default)]
+struct Section {
+    character: Option<String>,
+    player: Option<usize>,
+}
+
+/* `[character "Jax"]` -> ("character", "\"Jax\""), `[player 2]` -> ("player", "2") */
+fn section_header(raw_line: &str) -> Option<(&'static str, &str)> {
+    let line = raw_line.trim();
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    if line.contains("->") {
+        return None;
+    }
+    ["character", "player"].into_iter().find_map(|kind| {
+        inner
+            .strip_prefix(kind)
+            .filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+            .map(|rest| (kind, rest.trim()))
+    })
+}
+
+/* "set timeout_ms = 400" -> " timeout_ms = 400" */
+fn set_body(raw_line: &str) -> Option<&str> {
+    raw_line.trim_start().strip_prefix("set").filter(|rest| rest.starts_with(char::is_whitespace))
+}
+
+fn parse_setting(line: &SourceLine) -> Result<Setting, (ParseError, SourceLoc)> {
+    let (file, line_no, raw_line) = (line.file.as_deref(), line.line_no, line.text.as_str());
+    let body = set_body(raw_line).unwrap_or_default();
+    let body_start = raw_line.len() - body.len();
+    let invalid = |reason: String, span: std::ops::Range<usize>| {
+        (ParseError::InvalidSetting { line_no, reason }, SourceLoc::new(file, line_no, raw_line, span))
+    };
+
+    let (name, value) = body
+        .split_once('=')
+        .map(|(n, v)| (n.trim(), v.trim()))
+        .ok_or_else(|| invalid("expected `set name = value`".to_string(), body_start..raw_line.trim_end().len()))?;
+    check_setting(name, value).map_err(|reason| {
+        let at = raw_line[body_start..].find(name).map_or(body_start, |i| body_start + i);
+        invalid(reason, at..raw_line.trim_end().len())
+    })?;
 
-            let sequence: Vec<Token> = lhs
-                .split(',')
-                .map(|t| t.trim())
-                .filter(|t| !t.is_empty())
-                .map(Token::new)
-                .collect();
+    Ok(Setting {
+        name: name.to_string(),
+        value: value.to_string(),
+        player: line.section.player,
+        loc: SourceLoc::whole_line(file, line_no, raw_line),
+    })
+}
+
+/* `include "bindings/keyboard.gmr"` -> the text after the keyword */
+fn include_arg(raw_line: &str) -> Option<&str> {
+    raw_line
+        .trim_start()
+        .strip_prefix("include")
+        .filter(|rest| rest.starts_with(char::is_whitespace) && !rest.contains("->"))
+        .map(str::trim)
+}
+
+/* Files on the current include chain (for cycles) and every file read so
+ * far (each file is included once, so shared bindings are not duplicated). */
+#[derive(Debug, Default)]
+struct Includes {
+    stack: Vec<(PathBuf, String)>,
+    seen: BTreeSet<PathBuf>,
+    /* file names in the order they were read */
+    files: Vec<String>,
+}
+
+fn include_key(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
 
-            if sequence.is_empty() {
-                return Err(ParseError::EmptySequence { line_no });
+/* Flattens `input` into its lines, splicing every included file in place
+ * of its `include` line. Paths are relative to the including file.
+ * A `[character "Name"]` or `[player N]` header lasts until the next header
+ * of its kind or the end of its file; an included file starts in the
+ * includer's sections. */
+fn load_lines(
+    input: &str,
+    file: Option<&str>,
+    section: &Section,
+    includes: &mut Includes,
+    out: &mut Vec<SourceLine>,
+    errors: &mut Vec<(ParseError, SourceLoc)>,
+) {
+    let base = file.and_then(|f| Path::new(f).parent()).unwrap_or(Path::new(""));
+    includes.files.extend(file.map(str::to_string));
+    let mut section = section.clone();
+
+    for (line_no, raw_line) in rule_lines(input) {
+        if let Some((kind, arg)) = section_header(raw_line) {
+            let name = arg.strip_prefix('"').and_then(|a| a.strip_suffix('"')).map(str::trim).filter(|a| !a.is_empty());
+            let player = arg.parse::<usize>().ok().filter(|&n| n >= 1);
+            let reason = match (kind, name, player) {
+                ("character", Some(name), _) => {
+                    section.character = Some(name.to_string());
+                    continue;
+                }
+                ("character", None, _) => "expected a quoted name, e.g. [character \"Jax\"]",
+                (_, _, Some(n)) => {
+                    section.player = Some(n);
+                    continue;
+                }
+                _ => "expected a player number from 1, e.g. [player 2]",
+            };
+            let loc = SourceLoc::whole_line(file, line_no, raw_line);
+            errors.push((ParseError::InvalidSection { line_no, reason: reason.to_string() }, loc));
+            continue;
+        }
+        let Some(arg) = include_arg(raw_line) else {
+            out.push(SourceLine {
+                file: file.map(str::to_string),
+                line_no,
+                text: raw_line.to_string(),
+                section: section.clone(),
+            });
+            continue;
+        };
+        let arg_start = raw_line.len() - raw_line.trim_start().len() + "include".len();
+        let loc = || SourceLoc::new(file, line_no, raw_line, arg_start..raw_line.trim_end().len());
+
+        let Some(rel) = arg.strip_prefix('"').and_then(|a| a.strip_suffix('"')).filter(|a| !a.is_empty()) else {
+            let reason = "expected a quoted path, e.g. include \"bindings.gmr\"".to_string();
+            errors.push((ParseError::Include { line_no, reason }, loc()));
+            continue;
+        };
+        let path = base.join(rel);
+        let name = path.to_string_lossy().into_owned();
+        let key = include_key(&path);
+
+        if let Some(pos) = includes.stack.iter().position(|(k, _)| *k == key) {
+            let mut chain: Vec<String> = includes.stack[pos..].iter().map(|(_, n)| n.clone()).collect();
+            chain.push(name);
+            errors.push((ParseError::IncludeCycle { line_no, chain }, loc()));
+            continue;
+        }
+        if !includes.seen.insert(key.clone()) {
+            continue;
+        }
+        match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                includes.stack.push((key, name.clone()));
+                load_lines(&text, Some(&name), &section, includes, out, errors);
+                includes.stack.pop();
+            }
+            Err(e) => {
+                let reason = format!("cannot read {name}: {e}");
+                errors.push((ParseError::Include { line_no, reason }, loc()));
             }
+        }
+    }
+}
 
-            Ok(Rule { sequence, move_name: rhs.to_string() })
-        })
-        .collect::<Result<Vec<_>, _>>()?;
+/* Both passes over the flattened lines; errors come back in source order. */
+fn parse_lines(input: &str, file: Option<&str>) -> (Grammar, Vec<(ParseError, SourceLoc)>) {
+    let mut includes = Includes::default();
+    if let Some(f) = file {
+        let key = include_key(Path::new(f));
+        includes.seen.insert(key.clone());
+        includes.stack.push((key, f.to_string()));
+    }
+    let (mut lines, mut errors) = (Vec::new(), Vec::new());
+    load_lines(input, file, &Section::default(), &mut includes, &mut lines, &mut errors);
+    let (macros, macro_errors) = collect_macros(&lines);
+    errors.extend(macro_errors);
+
+    let (set_lines, rule_src): (Vec<&SourceLine>, Vec<&SourceLine>) = lines
+        .iter()
+        .filter(|line| let_body(&line.text).is_none())
+        .partition(|line| set_body(&line.text).is_some());
 
+    let (settings, setting_errors): (Vec<_>, Vec<_>) =
+        set_lines.into_iter().map(parse_setting).partition(Result::is_ok);
+    let (rules, rule_errors): (Vec<_>, Vec<_>) = rule_src
+        .into_iter()
+        .map(|line| parse_rule(line, &macros))
+        .partition(Result::is_ok);
+
+    errors.extend(setting_errors.into_iter().filter_map(Result::err));
+    errors.extend(rule_errors.into_iter().filter_map(Result::err));
+    /* by file in the order they were read, then by position */
+    errors.sort_by_key(|(_, loc)| {
+        let file_rank = loc.file.as_ref().and_then(|f| includes.files.iter().position(|g| g == f));
+        (file_rank, loc.line, loc.col)
+    });
+
+    let rules = rules.into_iter().filter_map(Result::ok).flatten().collect();
+    let settings = settings.into_iter().filter_map(Result::ok).collect();
+    (with_alphabet(rules, settings), errors)
+}
+
+fn with_alphabet(rules: Vec<Rule>, settings: Vec<Setting>) -> Grammar {
     let alphabet: Vec<Token> = rules
         .iter()
         .flat_map(|r| r.sequence.iter().cloned())
@@ -114,45 +729,129 @@ pub fn parse_gmr(input: &str) -> Result<Grammar, ParseError> {
         .into_iter()
         .collect();
 
-    Ok(Grammar { rules, alphabet })
+    Grammar { rules, alphabet, settings }
+}
+
+/*
+ * line := <sequence> "->" <move_name> [ "{" attr ("," attr)* "}" ]
+ * attr := name "=" (word | "\"" text "\"")     (see RULE_ATTRS)
+ *   max_gap / min_gap: ms allowed between consecutive steps; max_total: ms
+ *   from the first step to the last; priority: integer, higher wins when
+ *   several moves complete at once (see ResolutionPolicy); character,
+ *   damage, startup (frames), tags (comma-separated, quoted): MoveInfo
+ * sequence := item ("," item)*
+ * item := token ("*" count | "?")*             (repeat, optional)
+ *       | "(" sequence ("|" sequence)* ")"     (alternation, same postfixes)
+ * token := non-empty string without , ( ) | or newline (trimmed)
+ *        | "[" name "]" ("+" "[" name "]")+   (chord: pressed together, any order)
+ *        | "^" "[" name "]"                    (released)
+ *        | "[" name "]" "~" ms                 (held at least ms, completes on release)
+ *        | "$" name                            (macro reference)
+ * directions: [Fwd] [Back] [DownFwd] [DownBack] [UpFwd] [UpBack] follow the
+ *   player's side; in combos [Right]/[Left] are read as seen from the left side
+ * macro := "let" name "=" sequence             (may be used before it is defined)
+ * include := "include" "\"" path "\""          (relative to this file, read once)
+ * section := "[character" "\"" name "\"" "]"   (combos below belong to that character;
+ *                                               combos before any section belong to all)
+ *          | "[player" number "]"              (bindings and settings below are that player's)
+ * setting := "set" name "=" value              (see SETTINGS)
+ *   timeout_ms: step timeout; on_timeout: reset | failure_link | keep;
+ *   on_unknown_key: reset | ignore | noise (unbound keys become [?]);
+ *   resolution: all | highest_priority | longest_match | first_declared
+ * comments: lines starting with '#' (ignored)
+ * blank lines ignored
+ *
+ * Returns the first error in source order; see `parse_gmr_all` for a recovering parse.
+ */
+pub fn parse_gmr(input: &str) -> Result<Grammar, ParseError> {
+    first_error(input, None)
+}
+
+fn first_error(input: &str, file: Option<&str>) -> Result<Grammar, ParseError> {
+    let (grammar, errors) = parse_lines(input, file);
+    match errors.into_iter().next() {
+        Some((e, _)) => Err(e),
+        None => Ok(grammar),
+    }
+}
+
+/* Recovering parse: every bad line becomes a diagnostic and is skipped,
+ * so one pass reports all errors in the file. */
+pub fn parse_gmr_all(input: &str, file: Option<&str>) -> (Grammar, Vec<Diagnostic>) {
+    let (grammar, errors) = parse_lines(input, file);
+    let diags = errors
+        .into_iter()
+        .map(|(e, loc)| Diagnostic::error(loc, e.message()))
+        .collect();
+
+    (grammar, diags)
 }
 
 pub fn parse_gmr_file(path: &str) -> Result<Grammar, ParseError> {
     std::fs::read_to_string(path)
         .map_err(ParseError::Io)
-        .and_then(|s| parse_gmr(&s))
+        .and_then(|s| first_error(&s, Some(path)))
 }
 
-pub fn classify(g: &Grammar) -> CompiledGrammar {
+pub fn parse_gmr_file_all(path: &str) -> Result<(Grammar, Vec<Diagnostic>), ParseError> {
+    std::fs::read_to_string(path)
+        .map_err(ParseError::Io)
+        .map(|s| parse_gmr_all(&s, Some(path)))
+}
+
+enum RuleKind {
+    Binding,
+    Combo,
+    Discard(DiscardReason),
+}
+
+fn rule_kind(r: &Rule) -> RuleKind {
     #[inline]
-    fn is_internal(s: &str) -> bool { s.starts_with('[') && s.ends_with(']') }
+    fn is_internal(s: &str) -> bool { is_bracketed(split_step(s).1) }
+
+    let lhs_internal = r.sequence.iter().all(|t| is_internal(t.as_str()));
+    let lhs_any_internal = r.sequence.iter().any(|t| is_internal(t.as_str()));
+    let rhs_internal = is_internal(&r.move_name);
+    match (r.sequence.len(), lhs_internal, rhs_internal) {
+        (1, false, true) => RuleKind::Binding,
+        (_, true, false) => RuleKind::Combo,
+        (_, true, true) => RuleKind::Discard(DiscardReason::InternalToInternal),
+        _ if lhs_any_internal => RuleKind::Discard(DiscardReason::MixedSequence),
+        (_, false, true) => RuleKind::Discard(DiscardReason::MultiKeyBinding),
+        (_, false, false) => RuleKind::Discard(DiscardReason::UnbracketedCombo),
+    }
+}
 
+pub fn classify(g: &Grammar) -> CompiledGrammar {
     let bindings: Vec<Binding> = g
         .rules
         .iter()
-        .filter_map(|r| {
-            let lhs_internal = r.sequence.iter().all(|t| is_internal(t.as_str()));
-            let rhs_internal = is_internal(&r.move_name);
-            match (r.sequence.len(), lhs_internal, rhs_internal) {
-                (1, false, true) => Some(Binding {
-                    key: r.sequence[0].as_str().to_string(),
-                    internal: r.move_name.clone(),
-                }),
-                _ => None,
-            }
+        .filter_map(|r| match rule_kind(r) {
+            RuleKind::Binding => Some(Binding {
+                key: r.sequence[0].as_str().to_string(),
+                internal: r.move_name.clone(),
+                loc: r.loc.clone(),
+                player: r.player,
+            }),
+            _ => None,
         })
         .collect();
 
-        let combos: Vec<Rule> = g
+    let combos: Vec<Rule> = g
         .rules
         .iter()
-        .filter_map(|r| {
-            let lhs_internal = r.sequence.iter().all(|t| is_internal(t.as_str()));
-            let rhs_internal = is_internal(&r.move_name);
-            match (r.sequence.len(), lhs_internal, rhs_internal) {
-                (_, true, false) => Some(r.clone()),
-                _ => None,
-            }
+        .filter_map(|r| match rule_kind(r) {
+            RuleKind::Combo => Some(r.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let discarded: Vec<Discarded> = g
+        .rules
+        .iter()
+        .filter_map(|r| match rule_kind(r) {
+            RuleKind::Discard(reason) => Some(Discarded { rule: r.clone(), reason }),
+            _ => None,
         })
         .collect();
 
@@ -180,12 +879,15 @@ pub fn classify(g: &Grammar) -> CompiledGrammar {
         bindings,
         internal_alphabet,
         key_alphabet,
+        discarded,
+        settings: g.settings.clone(),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::attrs::Timing;
 
     #[test]
     fn basic_parse_test() {
@@ -205,6 +907,201 @@ mod tests {
         assert!(tokens.contains(&"Right"));
     }
 
+    #[test]
+    fn chords_are_canonical() {
+        let grammar = parse_gmr("[FP] + [BP], [Down] -> Chord Move").unwrap();
+        let seq: Vec<&str> = grammar.rules[0].sequence.iter().map(|t| t.as_str()).collect();
+        assert_eq!(seq, vec!["[BP]+[FP]", "[Down]"]);
+        assert!(grammar.rules[0].sequence[0].is_chord());
+        assert!(!grammar.rules[0].sequence[1].is_chord());
+        assert_eq!(chord_members("[+]"), vec!["[+]"]);
+    }
+
+    #[test]
+    fn release_and_hold_steps() {
+        let grammar = parse_gmr("[Back] ~ 800, ^ [FP], [FP] -> Charge").unwrap();
+        let seq: Vec<&str> = grammar.rules[0].sequence.iter().map(|t| t.as_str()).collect();
+        assert_eq!(seq, vec!["[Back]~800", "^[FP]", "[FP]"]);
+        let kinds: Vec<StepKind> = grammar.rules[0].sequence.iter().map(|t| t.step_kind()).collect();
+        assert_eq!(kinds, vec![StepKind::Hold(800), StepKind::Release, StepKind::Press]);
+        assert_eq!(split_step("[Back]~x"), (StepKind::Press, "[Back]~x"));
+    }
+
+    #[test]
+    fn classify_reports_discarded_rules() {
+        let g = parse_gmr("
+            [BP] -> Claw Slam
+            q -> [BP]
+            BP -> Claw Slam
+            [BP] -> [FP]
+            a, b -> [X]
+            [BP], FP -> Mixed
+            a, [X] -> [Y]
+        ").unwrap();
+        let compiled = classify(&g);
+        assert_eq!(compiled.combos.len(), 1);
+        assert_eq!(compiled.bindings.len(), 1);
+        let reasons: Vec<(usize, DiscardReason)> =
+            compiled.discarded.iter().map(|d| (d.rule.loc.line, d.reason)).collect();
+        assert_eq!(reasons, vec![
+            (4, DiscardReason::UnbracketedCombo),
+            (5, DiscardReason::InternalToInternal),
+            (6, DiscardReason::MultiKeyBinding),
+            (7, DiscardReason::MixedSequence),
+            (8, DiscardReason::MixedSequence),
+        ]);
+    }
+
+    #[test]
+    fn patterns_expand_into_rules() {
+        let grammar = parse_gmr("[Left]*2, [Down]?, ([FP]|[BP]) -> Finisher\n( -> [Open]").unwrap();
+        assert_eq!(grammar.rules.len(), 5);
+        assert!(grammar.rules[..4].iter().all(|r| r.move_name == "Finisher" && r.pattern == grammar.rules[0].pattern));
+        assert_eq!(grammar.rules[4].sequence, vec![Token::new("(")]);
+        match parse_gmr("[A], ([B] -> X") {
+            Err(ParseError::InvalidSequence { line_no: 1, reason }) => assert_eq!(reason, "unclosed '('"),
+            other => panic!("Unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn macros_expand_in_rules() {
+        let src = "[FP], $QCF -> Late Use\nlet QCF = [Down], [DownRight], [Right]\nlet HADOU = $QCF, [FP]\n$HADOU -> Hadouken";
+        let grammar = parse_gmr(src).unwrap();
+        assert_eq!(grammar.rules.len(), 2);
+        assert_eq!(grammar.rules[0].sequence.len(), 4);
+        assert_eq!(
+            grammar.rules[1].sequence,
+            ["[Down]", "[DownRight]", "[Right]", "[FP]"].map(Token::new).to_vec(),
+        );
+        assert!(matches!(&grammar.rules[1].pattern[0], Pattern::Macro { name, .. } if name == "HADOU"));
+    }
+
+    #[test]
+    fn macro_errors_point_at_definition_and_use() {
+        let src = "let A = [X], $B\nlet B = ($A | [Y])\n[Z], $A -> Loop\n[Z], $NOPE -> Missing\nlet 9x = [Q]";
+        let (grammar, diags) = parse_gmr_all(src, None);
+        assert!(grammar.rules.is_empty());
+        let found: Vec<(usize, usize, &str)> = diags.iter().map(|d| (d.loc.line, d.loc.col, d.message.as_str())).collect();
+        assert_eq!(found, vec![
+            (1, 5, "recursive macro: $A -> $B -> $A"),
+            (2, 5, "recursive macro: $B -> $A -> $B"),
+            (3, 6, "recursive macro: $A -> $B -> $A"),
+            (4, 6, "undefined macro `$NOPE`"),
+            (5, 5, "invalid macro: `9x` is not a valid macro name"),
+        ]);
+        match parse_gmr(src) {
+            Err(ParseError::RecursiveMacro { line_no: 1, .. }) => {}
+            other => panic!("Unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn macro_body_errors_point_at_whole_characters() {
+        let (_, diags) = parse_gmr_all("let Q = ([A])é", None);
+        assert_eq!((diags[0].loc.line, diags[0].loc.col, diags[0].loc.len), (1, 14, 1));
+    }
+
+    #[test]
+    fn includes_are_spliced_once() {
+        let g = parse_gmr_file("grammar/roster/main.gmr").unwrap();
+        let origin: Vec<(&str, Option<&str>)> = g
+            .rules
+            .iter()
+            .map(|r| (r.move_name.as_str(), r.loc.file.as_deref()))
+            .collect();
+        assert_eq!(origin, vec![
+            ("[Down]", Some("grammar/roster/bindings/keyboard.gmr")),
+            ("[Left]", Some("grammar/roster/bindings/keyboard.gmr")),
+            ("[Right]", Some("grammar/roster/bindings/keyboard.gmr")),
+            ("[BP]", Some("grammar/roster/bindings/keyboard.gmr")),
+            ("[FP]", Some("grammar/roster/bindings/keyboard.gmr")),
+            ("Fireball (Generic)", Some("grammar/roster/moves.gmr")),
+            ("Slide (Generic)", Some("grammar/roster/main.gmr")),
+        ]);
+    }
+
+    #[test]
+    fn include_cycles_and_missing_files_are_reported() {
+        let (g, diags) = parse_gmr_file_all("grammar/errors/include/a.gmr").unwrap();
+        assert_eq!(g.rules.len(), 3);
+        let found: Vec<String> = diags.iter().map(|d| format!("{}: {}", d.loc, d.message)).collect();
+        assert_eq!(found.len(), 2);
+        assert!(found[0].starts_with("grammar/errors/include/a.gmr:4:8: invalid include: cannot read grammar/errors/include/missing.gmr"));
+        assert_eq!(
+            found[1],
+            "grammar/errors/include/b.gmr:2:8: include cycle: \
+             grammar/errors/include/a.gmr -> grammar/errors/include/b.gmr -> grammar/errors/include/a.gmr",
+        );
+        assert!(matches!(
+            parse_gmr_file("grammar/errors/include/a.gmr"),
+            Err(ParseError::Include { line_no: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn character_sections_tag_rules() {
+        let src = "[A] -> Shared\n[character \"Jax\"]\n[A], [A] -> Grab\n[character Kano]\n[B] -> Knife";
+        let (g, diags) = parse_gmr_all(src, None);
+        let tags: Vec<(&str, Option<&str>)> =
+            g.rules.iter().map(|r| (r.move_name.as_str(), r.character.as_deref())).collect();
+        assert_eq!(tags, vec![("Shared", None), ("Grab", Some("Jax")), ("Knife", Some("Jax"))]);
+        assert_eq!(diags.len(), 1);
+        assert_eq!((diags[0].loc.line, diags[0].message.as_str()), (
+            4,
+            "invalid section: expected a quoted name, e.g. [character \"Jax\"]",
+        ));
+    }
+
+    #[test]
+    fn player_sections_and_settings() {
+        let src = "set timeout_ms = 300\na -> [A]\n[player 2]\nb -> [A]\nset timeout_ms = 900\n[player two]\nset speed = 3";
+        let (g, diags) = parse_gmr_all(src, None);
+        let compiled = classify(&g);
+        let players: Vec<Option<usize>> = compiled.bindings.iter().map(|b| b.player).collect();
+        assert_eq!(players, vec![None, Some(2)]);
+        let settings: Vec<(&str, Option<usize>)> =
+            g.settings.iter().map(|s| (s.value.as_str(), s.player)).collect();
+        assert_eq!(settings, vec![("300", None), ("900", Some(2))]);
+        assert_eq!(compiled.players(), 2);
+        let found: Vec<(usize, &str)> = diags.iter().map(|d| (d.loc.line, d.message.as_str())).collect();
+        assert_eq!(found, vec![
+            (6, "invalid section: expected a player number from 1, e.g. [player 2]"),
+            (7, "invalid setting: unknown setting `speed` (expected one of: timeout_ms, on_timeout, on_unknown_key, resolution)"),
+        ]);
+    }
+
+    #[test]
+    fn timing_attributes() {
+        let src = "[A], [B] -> Quick {max_gap=100, max_total=250}\n[A] -> Plain\n[A] -> Bad {min_gap=90, max_gap=50}\n[B] -> Odd {speed=2}";
+        let (g, diags) = parse_gmr_all(src, None);
+        let timings: Vec<(&str, Timing)> = g.rules.iter().map(|r| (r.move_name.as_str(), r.attrs.timing)).collect();
+        assert_eq!(timings, vec![
+            ("Quick", Timing { max_gap: Some(100), min_gap: None, max_total: Some(250) }),
+            ("Plain", Timing::default()),
+        ]);
+        let found: Vec<(usize, usize, &str)> = diags.iter().map(|d| (d.loc.line, d.loc.col, d.message.as_str())).collect();
+        assert_eq!(found, vec![
+            (3, 13, "invalid attribute: min_gap (90) is larger than max_gap (50)"),
+            (4, 13, "invalid attribute: unknown attribute `speed` (expected one of: max_gap, min_gap, max_total, priority, character, damage, startup, tags)"),
+        ]);
+
+        let g = parse_gmr("[A] -> Top {priority=-2, max_gap=90}").unwrap();
+        assert_eq!(g.rules[0].attrs.priority, -2);
+        assert_eq!(g.rules[0].attrs.timing.max_gap, Some(90));
+
+        let src = "[A] -> Fireball {character=Generic, damage=12, startup=9, tags=\"projectile, ex\"}\n\
+                   [character \"Jax\"]\n[A] -> Slam {damage=20}";
+        let infos: Vec<MoveInfo> = parse_gmr(src).unwrap().rules.into_iter().map(|r| r.attrs.info).collect();
+        assert_eq!(infos[0], MoveInfo {
+            character: Some("Generic".to_string()),
+            damage: Some(12),
+            startup: Some(9),
+            tags: vec!["projectile".to_string(), "ex".to_string()],
+        });
+        assert_eq!((infos[1].character.as_deref(), infos[1].damage), (Some("Jax"), Some(20)));
+    }
+
     #[test]
     fn missing_arrow_line12() {
         let grammar = parse_gmr_file("grammar/errors/missing_arrow.gmr");
@@ -226,6 +1123,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn recovering_parse_reports_every_error() {
+        let path = "grammar/errors/empty_sequence.gmr";
+        let (grammar, diags) = parse_gmr_file_all(path).unwrap();
+        assert_eq!(grammar.rules.len(), 5);
+        let found: Vec<(usize, usize, usize, &str)> = diags
+            .iter()
+            .map(|d| (d.loc.line, d.loc.col, d.loc.len, d.message.as_str()))
+            .collect();
+        assert_eq!(found, vec![
+            (3, 2, 2, "empty sequence before '->'"),
+            (6, 1, 26, "expected '->' in rule"),
+        ]);
+        assert!(diags.iter().all(|d| d.loc.file.as_deref() == Some(path)));
+        assert!(diags[0].render().contains(" --> grammar/errors/empty_sequence.gmr:3:2\n"));
+    }
+
+    #[test]
+    fn sequence_errors_point_at_whole_characters() {
+        let (_, diags) = parse_gmr_all("[A], ([B])é -> X", None);
+        assert_eq!((diags[0].loc.line, diags[0].loc.col, diags[0].loc.len), (1, 11, 1));
+    }
+
+    #[test]
+    fn empty_move_name_points_at_arrow() {
+        let (_, diags) = parse_gmr_all("[A], [B] ->   ", None);
+        assert_eq!((diags[0].loc.line, diags[0].loc.col, diags[0].loc.len), (1, 10, 2));
+    }
+
     #[test]
     fn invalid_file() {
         let grammar = parse_gmr_file("non_existent.gmr");
@@ -0,0 +1,419 @@
+use crate::parse::Token;
+
+/* Upper bound on the sequences one rule may expand into. */
+pub const MAX_VARIANTS: usize = 1024;
+
+/* Upper bound on the steps of one expanded sequence, and so on a repeat
+ * count. */
+pub const MAX_STEPS: usize = 256;
+
+/* A combo's left-hand side as written, before expansion. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    Step(Token),
+    /* `[Left]*3` */
+    Repeat(Box<Pattern>, usize),
+    /* `[Down]?` */
+    Optional(Box<Pattern>),
+    /* `([FP] | [BP])`, each alternative a sub-sequence */
+    Alt(Vec<Vec<Pattern>>),
+    /* `$QCF`, already resolved to its definition */
+    Macro { name: String, body: Vec<Pattern> },
+}
+
+/* Message and byte offset into the parsed text. */
+pub type PatternError = (String, usize);
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) {
+        self.pos += self.peek().map_or(0, char::len_utf8);
+    }
+
+    fn skip_ws(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    /* item ("," item)*, stopping before ')' / '|' / end */
+    fn seq(&mut self) -> Result<Vec<Pattern>, PatternError> {
+        let mut items = Vec::new();
+        loop {
+            if let Some(p) = self.item()? {
+                items.push(p);
+            }
+            self.skip_ws();
+            match self.peek() {
+                Some(',') => self.bump(),
+                None | Some(')') | Some('|') => return Ok(items),
+                Some(c) => return Err((format!("expected ',' before '{c}'"), self.pos)),
+            }
+        }
+    }
+
+    fn item(&mut self) -> Result<Option<Pattern>, PatternError> {
+        self.skip_ws();
+        match self.peek() {
+            Some('(') => {
+                let open = self.pos;
+                self.bump();
+                let mut alts = vec![self.seq()?];
+                while self.peek() == Some('|') {
+                    self.bump();
+                    alts.push(self.seq()?);
+                }
+                if self.peek() != Some(')') {
+                    return Err(("unclosed '('".to_string(), open));
+                }
+                if alts.iter().any(Vec::is_empty) {
+                    return Err(("empty alternative in group".to_string(), open));
+                }
+                self.bump();
+                self.postfix(Pattern::Alt(alts)).map(Some)
+            }
+            _ => self.step(),
+        }
+    }
+
+    /* A plain token, with any trailing "*N" / "?" peeled off. */
+    fn step(&mut self) -> Result<Option<Pattern>, PatternError> {
+        let start = self.pos;
+        let mut in_bracket = false;
+        while let Some(c) = self.peek() {
+            match c {
+                '[' => in_bracket = true,
+                ']' => in_bracket = false,
+                ',' | '|' | '(' | ')' if !in_bracket => break,
+                _ => {}
+            }
+            self.bump();
+        }
+        let raw = self.src[start..self.pos].trim();
+        if raw.is_empty() {
+            return Ok(None);
+        }
+
+        let mut base = raw;
+        let mut ops = Vec::new();
+        loop {
+            if let Some(rest) = base.strip_suffix('?').filter(|r| !r.trim().is_empty()) {
+                ops.push(None);
+                base = rest.trim_end();
+            } else if let Some((rest, n)) = base.rsplit_once('*').filter(|(r, n)| {
+                !r.trim().is_empty() && !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())
+            }) {
+                let n = repeat_count(n).map_err(|reason| (reason, start))?;
+                ops.push(Some(n));
+                base = rest.trim_end();
+            } else {
+                break;
+            }
+        }
+
+        Ok(Some(ops.into_iter().rev().fold(Pattern::Step(Token::new(base)), |p, op| match op {
+            Some(n) => Pattern::Repeat(Box::new(p), n),
+            None => Pattern::Optional(Box::new(p)),
+        })))
+    }
+
+    fn postfix(&mut self, mut p: Pattern) -> Result<Pattern, PatternError> {
+        loop {
+            self.skip_ws();
+            match self.peek() {
+                Some('?') => {
+                    self.bump();
+                    p = Pattern::Optional(Box::new(p));
+                }
+                Some('*') => {
+                    let at = self.pos;
+                    self.bump();
+                    let digits_start = self.pos;
+                    while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                        self.bump();
+                    }
+                    let digits = &self.src[digits_start..self.pos];
+                    if digits.is_empty() {
+                        return Err(("expected a repeat count after '*'".to_string(), at));
+                    }
+                    let n = repeat_count(digits).map_err(|reason| (reason, at))?;
+                    p = Pattern::Repeat(Box::new(p), n);
+                }
+                _ => return Ok(p),
+            }
+        }
+    }
+}
+
+/* The count of "*N", from 1 to MAX_STEPS. */
+fn repeat_count(digits: &str) -> Result<usize, String> {
+    match digits.parse::<usize>() {
+        Ok(0) => Err("repeat count must be at least 1".to_string()),
+        Ok(n) if n <= MAX_STEPS => Ok(n),
+        _ => Err(format!("repeat count must be at most {MAX_STEPS}")),
+    }
+}
+
+/*
+ * sequence := item ("," item)*
+ * item := atom ("*" count | "?")*
+ * atom := token | "(" sequence ("|" sequence)* ")"
+ */
+pub fn parse_pattern(src: &str) -> Result<Vec<Pattern>, PatternError> {
+    let mut p = Parser { src, pos: 0 };
+    let items = p.seq()?;
+    match p.peek() {
+        None => Ok(items),
+        Some(')') => Err(("unmatched ')'".to_string(), p.pos)),
+        Some(c) => Err((format!("'{c}' outside of a group"), p.pos)),
+    }
+}
+
+fn concat(prefixes: Vec<Vec<Token>>, suffixes: &[Vec<Token>]) -> Result<Vec<Vec<Token>>, String> {
+    if prefixes.len().saturating_mul(suffixes.len()) > MAX_VARIANTS {
+        return Err(format!("expands to more than {MAX_VARIANTS} sequences"));
+    }
+    Ok(prefixes
+        .iter()
+        .flat_map(|p| suffixes.iter().map(move |s| p.iter().chain(s).cloned().collect()))
+        .collect())
+}
+
+fn expand_one(p: &Pattern) -> Result<Vec<Vec<Token>>, String> {
+    match p {
+        Pattern::Step(t) => Ok(vec![vec![t.clone()]]),
+        Pattern::Repeat(inner, n) => {
+            let once = expand_one(inner)?;
+            let count = u32::try_from(*n)
+                .ok()
+                .and_then(|n| once.len().checked_pow(n))
+                .filter(|&c| c <= MAX_VARIANTS)
+                .ok_or_else(|| format!("expands to more than {MAX_VARIANTS} sequences"))?;
+            /* sequence k picks the variants spelled by k in base once.len(),
+             * the first repetition varying slowest, as `concat` orders them */
+            Ok((0..count)
+                .map(|k| {
+                    let mut picks: Vec<usize> = (0..*n)
+                        .scan(k, |rest, _| {
+                            let pick = *rest % once.len();
+                            *rest /= once.len();
+                            Some(pick)
+                        })
+                        .collect();
+                    picks.reverse();
+                    picks.into_iter().flat_map(|i| once[i].iter().cloned()).collect()
+                })
+                .collect())
+        }
+        Pattern::Optional(inner) => {
+            let mut v = expand_one(inner)?;
+            v.push(Vec::new());
+            Ok(v)
+        }
+        Pattern::Macro { body, .. } => expand(body),
+        Pattern::Alt(alts) => alts.iter().try_fold(Vec::new(), |mut acc, alt| {
+            acc.extend(expand(alt)?);
+            if acc.len() > MAX_VARIANTS {
+                return Err(format!("expands to more than {MAX_VARIANTS} sequences"));
+            }
+            Ok(acc)
+        }),
+    }
+}
+
+/* Steps in the longest sequence `items` expand into, saturating. */
+fn longest(items: &[Pattern]) -> usize {
+    items.iter().map(longest_one).fold(0, usize::saturating_add)
+}
+
+fn longest_one(p: &Pattern) -> usize {
+    match p {
+        Pattern::Step(_) => 1,
+        Pattern::Repeat(inner, n) => longest_one(inner).saturating_mul(*n),
+        Pattern::Optional(inner) => longest_one(inner),
+        Pattern::Macro { body, .. } => longest(body),
+        Pattern::Alt(alts) => alts.iter().map(|alt| longest(alt)).max().unwrap_or(0),
+    }
+}
+
+/* Every concrete sequence the pattern stands for, in written order,
+ * without duplicates. May include the empty sequence. Refused, before
+ * anything is built, if some sequence would be longer than MAX_STEPS. */
+pub fn expand(items: &[Pattern]) -> Result<Vec<Vec<Token>>, String> {
+    if longest(items) > MAX_STEPS {
+        return Err(format!("expands to sequences of more than {MAX_STEPS} steps"));
+    }
+    let all = items
+        .iter()
+        .try_fold(vec![Vec::new()], |acc, p| concat(acc, &expand_one(p)?))?;
+    let mut seen = std::collections::BTreeSet::new();
+    Ok(all.into_iter().filter(|v| seen.insert(v.clone())).collect())
+}
+
+/* Compact form as written; `label` renders each plain token. Macros show
+ * as `$NAME`, or as their steps when `expand_macros` is set. */
+pub fn render_pattern(items: &[Pattern], expand_macros: bool, label: &dyn Fn(&str) -> String) -> String {
+    fn one(p: &Pattern, expand_macros: bool, label: &dyn Fn(&str) -> String) -> String {
+        let operand = |inner: &Pattern| match inner {
+            Pattern::Macro { body, .. } if expand_macros && body.len() > 1 => {
+                format!("({})", render_pattern(body, expand_macros, label))
+            }
+            _ => one(inner, expand_macros, label),
+        };
+        match p {
+            Pattern::Step(t) => label(t.as_str()),
+            Pattern::Repeat(inner, n) => format!("{}*{n}", operand(inner)),
+            Pattern::Optional(inner) => format!("{}?", operand(inner)),
+            Pattern::Alt(alts) => format!(
+                "({})",
+                alts.iter().map(|a| render_pattern(a, expand_macros, label)).collect::<Vec<_>>().join(" | ")
+            ),
+            Pattern::Macro { body, .. } if expand_macros => render_pattern(body, expand_macros, label),
+            Pattern::Macro { name, .. } => format!("${name}"),
+        }
+    }
+    items.iter().map(|p| one(p, expand_macros, label)).collect::<Vec<_>>().join(", ")
+}
+
+/* Macro names referenced as `$NAME` steps, in order of appearance. */
+pub fn macro_refs(items: &[Pattern]) -> Vec<&str> {
+    items
+        .iter()
+        .flat_map(|p| match p {
+            Pattern::Step(t) => t.as_str().strip_prefix('$').into_iter().collect(),
+            Pattern::Repeat(inner, _) | Pattern::Optional(inner) => macro_refs(std::slice::from_ref(inner.as_ref())),
+            Pattern::Alt(alts) => alts.iter().flat_map(|a| macro_refs(a)).collect(),
+            Pattern::Macro { .. } => Vec::new(),
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MacroError {
+    Undefined(String),
+    /* the cycle, starting and ending with the same name */
+    Recursive(Vec<String>),
+}
+
+/* Replaces `$NAME` steps with `Pattern::Macro`, following nested macros.
+ * `stack` holds the names being expanded, to detect recursion. */
+pub fn resolve_macros(
+    items: &[Pattern],
+    defs: &std::collections::BTreeMap<String, Vec<Pattern>>,
+    stack: &mut Vec<String>,
+) -> Result<Vec<Pattern>, MacroError> {
+    items
+        .iter()
+        .map(|p| match p {
+            Pattern::Step(t) => match t.as_str().strip_prefix('$') {
+                None => Ok(p.clone()),
+                Some(name) => {
+                    if let Some(pos) = stack.iter().position(|n| n == name) {
+                        let mut cycle = stack[pos..].to_vec();
+                        cycle.push(name.to_string());
+                        return Err(MacroError::Recursive(cycle));
+                    }
+                    let def = defs.get(name).ok_or_else(|| MacroError::Undefined(name.to_string()))?;
+                    stack.push(name.to_string());
+                    let body = resolve_macros(def, defs, stack);
+                    stack.pop();
+                    Ok(Pattern::Macro { name: name.to_string(), body: body? })
+                }
+            },
+            Pattern::Repeat(inner, n) => resolve_macros(std::slice::from_ref(inner.as_ref()), defs, stack)
+                .map(|mut v| Pattern::Repeat(Box::new(v.remove(0)), *n)),
+            Pattern::Optional(inner) => resolve_macros(std::slice::from_ref(inner.as_ref()), defs, stack)
+                .map(|mut v| Pattern::Optional(Box::new(v.remove(0)))),
+            Pattern::Alt(alts) => alts
+                .iter()
+                .map(|a| resolve_macros(a, defs, stack))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Pattern::Alt),
+            Pattern::Macro { .. } => Ok(p.clone()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strs(v: Vec<Vec<Token>>) -> Vec<String> {
+        v.iter()
+            .map(|s| s.iter().map(|t| t.as_str()).collect::<Vec<_>>().join(", "))
+            .collect()
+    }
+
+    #[test]
+    fn repeat_optional_and_alternation() {
+        let p = parse_pattern("[Down]?, [Left]*2, ([FP] | [BP], [BK])").unwrap();
+        assert_eq!(strs(expand(&p).unwrap()), vec![
+            "[Down], [Left], [Left], [FP]",
+            "[Down], [Left], [Left], [BP], [BK]",
+            "[Left], [Left], [FP]",
+            "[Left], [Left], [BP], [BK]",
+        ]);
+        assert_eq!(render_pattern(&p, false, &|t| t.to_string()), "[Down]?, [Left]*2, ([FP] | [BP], [BK])");
+    }
+
+    #[test]
+    fn postfix_on_groups_and_special_tokens() {
+        let p = parse_pattern("([A]|[B])*2, [?], [A]+[B]?").unwrap();
+        assert_eq!(expand(&p).unwrap().len(), 8);
+        assert_eq!(render_pattern(&p, false, &|t| t.to_string()), "([A] | [B])*2, [?], [A]+[B]?");
+    }
+
+    #[test]
+    fn malformed_patterns() {
+        assert_eq!(parse_pattern("([A], [B]").unwrap_err().0, "unclosed '('");
+        assert_eq!(parse_pattern("[A])").unwrap_err(), ("unmatched ')'".to_string(), 3));
+        assert_eq!(parse_pattern("[A]*0").unwrap_err().0, "repeat count must be at least 1");
+        assert_eq!(parse_pattern("([A]|)").unwrap_err().0, "empty alternative in group");
+        assert!(parse_pattern("([A]|[B]) [C]").is_err());
+        let huge = parse_pattern("([A]|[B])*11").unwrap();
+        assert!(expand(&huge).is_err());
+    }
+
+    #[test]
+    fn repeats_are_bounded() {
+        assert_eq!(parse_pattern("[A]*99999999999").unwrap_err(), ("repeat count must be at most 256".to_string(), 0));
+        assert_eq!(parse_pattern("([A])*257").unwrap_err(), ("repeat count must be at most 256".to_string(), 5));
+        let nested = parse_pattern("([A]*200)*200").unwrap();
+        assert_eq!(expand(&nested).unwrap_err(), "expands to sequences of more than 256 steps");
+
+        let longest = expand(&parse_pattern("[B], [A]*255").unwrap()).unwrap();
+        assert_eq!((longest.len(), longest[0].len()), (1, 256));
+        let p = parse_pattern("([A] | [B])*2").unwrap();
+        assert_eq!(strs(expand(&p).unwrap()), vec!["[A], [A]", "[A], [B]", "[B], [A]", "[B], [B]"]);
+    }
+
+    #[test]
+    fn macros_resolve_and_render_both_ways() {
+        let defs = std::collections::BTreeMap::from([
+            ("QCF".to_string(), parse_pattern("[Down], [DownRight], [Right]").unwrap()),
+            ("HADOU".to_string(), parse_pattern("$QCF, [FP]").unwrap()),
+            ("A".to_string(), parse_pattern("[X], $B").unwrap()),
+            ("B".to_string(), parse_pattern("($A | [Y])").unwrap()),
+        ]);
+        let p = resolve_macros(&parse_pattern("$HADOU, $QCF?").unwrap(), &defs, &mut Vec::new()).unwrap();
+        assert_eq!(render_pattern(&p, false, &|t| t.to_string()), "$HADOU, $QCF?");
+        assert_eq!(
+            render_pattern(&p, true, &|t| t.to_string()),
+            "[Down], [DownRight], [Right], [FP], ([Down], [DownRight], [Right])?",
+        );
+        assert_eq!(expand(&p).unwrap().len(), 2);
+
+        let err = resolve_macros(&parse_pattern("$A").unwrap(), &defs, &mut Vec::new()).unwrap_err();
+        assert_eq!(err, MacroError::Recursive(vec!["A".into(), "B".into(), "A".into()]));
+        let err = resolve_macros(&parse_pattern("$NOPE").unwrap(), &defs, &mut Vec::new()).unwrap_err();
+        assert_eq!(err, MacroError::Undefined("NOPE".into()));
+    }
+}
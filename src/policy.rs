@@ -0,0 +1,151 @@
+use std::fmt;
+use std::str::FromStr;
+
+/* Internal token an unbound key becomes under `UnknownKeyPolicy::Noise`;
+ * a combo may use it to mean "any unbound key". */
+pub const NOISE: &str = "[?]";
+
+/* What a step arriving after the timeout does to a combo in progress. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TimeoutPolicy {
+    /* start over from the root */
+    #[default]
+    Reset,
+    /* drop the oldest steps: fall back one failure link, to the longest
+     * proper suffix that is still a combo prefix */
+    FailureLink,
+    /* carry on as if no time had passed */
+    Keep,
+}
+
+/* What a key with no binding does. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum UnknownKeyPolicy {
+    /* back to the root */
+    #[default]
+    Reset,
+    /* as if it was never pressed */
+    Ignore,
+    /* fed to the automaton as `NOISE`, like any other step */
+    Noise,
+}
+
+/* Which of the moves completing on the same step fire, and in what order. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ResolutionPolicy {
+    /* every one, in declaration order */
+    #[default]
+    All,
+    /* those with the highest `priority` */
+    HighestPriority,
+    /* those with the most steps */
+    LongestMatch,
+    /* the one declared first */
+    FirstDeclared,
+}
+
+impl TimeoutPolicy {
+    pub const NAMES: &'static [&'static str] = &["reset", "failure_link", "keep"];
+}
+
+impl UnknownKeyPolicy {
+    pub const NAMES: &'static [&'static str] = &["reset", "ignore", "noise"];
+}
+
+impl ResolutionPolicy {
+    pub const NAMES: &'static [&'static str] = &["all", "highest_priority", "longest_match", "first_declared"];
+}
+
+fn unknown(what: &str, value: &str, names: &[&str]) -> String {
+    format!("unknown {what} `{value}` (expected one of: {})", names.join(", "))
+}
+
+impl FromStr for TimeoutPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "reset" => Ok(TimeoutPolicy::Reset),
+            "failure_link" => Ok(TimeoutPolicy::FailureLink),
+            "keep" => Ok(TimeoutPolicy::Keep),
+            _ => Err(unknown("timeout policy", s, Self::NAMES)),
+        }
+    }
+}
+
+impl FromStr for UnknownKeyPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "reset" => Ok(UnknownKeyPolicy::Reset),
+            "ignore" => Ok(UnknownKeyPolicy::Ignore),
+            "noise" => Ok(UnknownKeyPolicy::Noise),
+            _ => Err(unknown("unknown-key policy", s, Self::NAMES)),
+        }
+    }
+}
+
+impl FromStr for ResolutionPolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "all" => Ok(ResolutionPolicy::All),
+            "highest_priority" => Ok(ResolutionPolicy::HighestPriority),
+            "longest_match" => Ok(ResolutionPolicy::LongestMatch),
+            "first_declared" => Ok(ResolutionPolicy::FirstDeclared),
+            _ => Err(unknown("resolution policy", s, Self::NAMES)),
+        }
+    }
+}
+
+impl fmt::Display for TimeoutPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TimeoutPolicy::Reset => "reset",
+            TimeoutPolicy::FailureLink => "failure_link",
+            TimeoutPolicy::Keep => "keep",
+        })
+    }
+}
+
+impl fmt::Display for UnknownKeyPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UnknownKeyPolicy::Reset => "reset",
+            UnknownKeyPolicy::Ignore => "ignore",
+            UnknownKeyPolicy::Noise => "noise",
+        })
+    }
+}
+
+impl fmt::Display for ResolutionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ResolutionPolicy::All => "all",
+            ResolutionPolicy::HighestPriority => "highest_priority",
+            ResolutionPolicy::LongestMatch => "longest_match",
+            ResolutionPolicy::FirstDeclared => "first_declared",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for name in TimeoutPolicy::NAMES {
+            assert_eq!(name.parse::<TimeoutPolicy>().unwrap().to_string(), *name);
+        }
+        for name in UnknownKeyPolicy::NAMES {
+            assert_eq!(name.parse::<UnknownKeyPolicy>().unwrap().to_string(), *name);
+        }
+        for name in ResolutionPolicy::NAMES {
+            assert_eq!(name.parse::<ResolutionPolicy>().unwrap().to_string(), *name);
+        }
+        assert_eq!(
+            "drop".parse::<TimeoutPolicy>().unwrap_err(),
+            "unknown timeout policy `drop` (expected one of: reset, failure_link, keep)"
+        );
+    }
+}
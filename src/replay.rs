@@ -0,0 +1,375 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::engine::{EngineOptions, Pinned, StepOutcome};
+use crate::input::{InputEvent, KeyEventKind};
+use crate::parse::CompiledGrammar;
+use crate::versus::{flip_all, next_characters, step_versus_outcome, tick_versus, VersusConfig, VersusState};
+
+/* First line of every replay file; the number goes up when the format changes. */
+pub const MAGIC: &str = "ft_ality-replay";
+pub const VERSION: u32 = 1;
+
+/* FNV-1a, 64 bits: stable across builds and platforms, unlike `DefaultHasher`. */
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| (h ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/* Hash of what the engine is built from: bindings, combos with their
+ * attributes, and settings. Comments, layout and how the grammar is split
+ * into included files do not change it. */
+pub fn grammar_hash(compiled: &CompiledGrammar) -> u64 {
+    let bindings = compiled.bindings.iter().map(|b| format!("bind {:?} {} {}\n", b.player, b.key, b.internal));
+    let combos = compiled.combos.iter().map(|r| {
+        let steps: Vec<&str> = r.sequence.iter().map(|t| t.as_str()).collect();
+        format!("combo {:?} {:?} {} -> {} {:?}\n", r.player, r.character, steps.join(","), r.move_name, r.attrs)
+    });
+    let settings = compiled.settings.iter().map(|s| format!("set {:?} {} {}\n", s.player, s.name, s.value));
+    let canonical: String = bindings.chain(combos).chain(settings).collect();
+    fnv1a(canonical.as_bytes())
+}
+
+/* Something the player did, as the frontends see it. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Key(KeyEventKind, String),
+    NextCharacter,
+    Flip,
+}
+
+/* A move some player fired; `player` is 0-based, like `VersusState::players`. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedMove {
+    pub time: u128,
+    pub player: usize,
+    pub name: String,
+}
+
+/* One session: what it ran against, every action with its time in ms, and
+ * the moves that fired. Text form:
+ *
+ *   ft_ality-replay 1
+ *   grammar 9c3e0b1f5a2d7e44
+ *   option timeout_ms 500
+ *   option chord_ms 50
+ *   option deferred false
+ *   1200 press down
+ *   1260 release down
+ *   1900 next
+ *   2100 flip
+ *   2350 move 1 Fireball
+ *   4000 end
+ *
+ * `move` lines give the 1-based player, then the move name; `end` is when
+ * recording stopped. Lines starting with '#' and blank lines are ignored.
+ * Options a grammar can also set (timeout_ms, on_timeout, on_unknown_key,
+ * resolution) are only written when given on the command line, and read
+ * back as such, so the grammar's `set` lines apply as they did live; the
+ * hash pins the grammar down. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replay {
+    pub grammar_hash: u64,
+    pub options: EngineOptions,
+    pub actions: Vec<(u128, Action)>,
+    pub moves: Vec<RecordedMove>,
+    pub end_ms: u128,
+}
+
+impl Replay {
+    pub fn new(grammar_hash: u64, options: &EngineOptions) -> Self {
+        Replay { grammar_hash, options: *options, actions: Vec::new(), moves: Vec::new(), end_ms: 0 }
+    }
+
+    pub fn push_action(&mut self, time: u128, action: Action) {
+        self.actions.push((time, action));
+        self.end_ms = self.end_ms.max(time);
+    }
+
+    /* Every move in `outcomes`, filtered or not. */
+    pub fn push_moves(&mut self, time: u128, outcomes: &[(usize, StepOutcome)]) {
+        let moves = outcomes
+            .iter()
+            .flat_map(|(player, o)| o.moves.iter().map(move |m| RecordedMove { time, player: *player, name: m.name.clone() }));
+        self.moves.extend(moves);
+        self.end_ms = self.end_ms.max(time);
+    }
+
+    pub fn finish(&mut self, end_ms: u128) {
+        self.end_ms = self.end_ms.max(end_ms);
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.to_string()).map_err(|e| format!("{path}: {e}"))
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        text.parse().map_err(|e| format!("{path}: {e}"))
+    }
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let o = &self.options;
+        writeln!(f, "{MAGIC} {VERSION}")?;
+        writeln!(f, "grammar {:016x}", self.grammar_hash)?;
+        if o.pinned.step_timeout {
+            writeln!(f, "option timeout_ms {}", o.step_timeout.as_millis())?;
+        }
+        writeln!(f, "option chord_ms {}", o.chord_window.as_millis())?;
+        if o.pinned.on_timeout {
+            writeln!(f, "option on_timeout {}", o.on_timeout)?;
+        }
+        if o.pinned.on_unknown_key {
+            writeln!(f, "option on_unknown_key {}", o.on_unknown_key)?;
+        }
+        writeln!(f, "option deferred {}", o.deferred)?;
+        if o.pinned.resolution {
+            writeln!(f, "option resolution {}", o.resolution)?;
+        }
+
+        /* actions and moves merged by time; a move comes after the action
+         * that fired it */
+        let (mut actions, mut moves) = (self.actions.iter().peekable(), self.moves.iter().peekable());
+        loop {
+            let take_action = match (actions.peek(), moves.peek()) {
+                (Some((ta, _)), Some(m)) => *ta <= m.time,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            if take_action {
+                let (time, action) = actions.next().expect("peeked");
+                match action {
+                    Action::Key(KeyEventKind::Press, key) => writeln!(f, "{time} press {key}")?,
+                    Action::Key(KeyEventKind::Release, key) => writeln!(f, "{time} release {key}")?,
+                    Action::NextCharacter => writeln!(f, "{time} next")?,
+                    Action::Flip => writeln!(f, "{time} flip")?,
+                }
+            } else {
+                let m = moves.next().expect("peeked");
+                writeln!(f, "{} move {} {}", m.time, m.player + 1, m.name)?;
+            }
+        }
+        writeln!(f, "{} end", self.end_ms)
+    }
+}
+
+fn parse_option(opts: EngineOptions, name: &str, value: &str) -> Result<EngineOptions, String> {
+    let ms = || value.parse::<u64>().map(Duration::from_millis).map_err(|_| format!("{name} expects milliseconds, found `{value}`"));
+    let p = opts.pinned;
+    Ok(match name {
+        "timeout_ms" => EngineOptions { step_timeout: ms()?, pinned: Pinned { step_timeout: true, ..p }, ..opts },
+        "chord_ms" => EngineOptions { chord_window: ms()?, ..opts },
+        "on_timeout" => EngineOptions { on_timeout: value.parse()?, pinned: Pinned { on_timeout: true, ..p }, ..opts },
+        "on_unknown_key" => {
+            EngineOptions { on_unknown_key: value.parse()?, pinned: Pinned { on_unknown_key: true, ..p }, ..opts }
+        }
+        "deferred" => EngineOptions {
+            deferred: value.parse().map_err(|_| format!("deferred expects true or false, found `{value}`"))?,
+            ..opts
+        },
+        "resolution" => EngineOptions { resolution: value.parse()?, pinned: Pinned { resolution: true, ..p }, ..opts },
+        _ => return Err(format!("unknown option `{name}`")),
+    })
+}
+
+impl FromStr for Replay {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(idx, raw)| (idx + 1, raw.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+        let version = lines
+            .next()
+            .and_then(|(_, l)| l.strip_prefix(MAGIC))
+            .map(str::trim)
+            .ok_or(format!("not a replay file (expected `{MAGIC} {VERSION}` first)"))?;
+        if version != VERSION.to_string() {
+            return Err(format!("unsupported replay version {version} (this build reads version {VERSION})"));
+        }
+
+        let mut replay = Replay::new(0, &EngineOptions::default());
+        let mut hash = None;
+        for (line_no, line) in lines {
+            let err = |reason: String| format!("line {line_no}: {reason}");
+            let mut parts = line.splitn(3, char::is_whitespace);
+            let (first, second, rest) = (parts.next().unwrap_or(""), parts.next(), parts.next().map(str::trim));
+            match (first, second, rest) {
+                ("grammar", Some(h), None) => {
+                    hash = Some(u64::from_str_radix(h, 16).map_err(|_| err(format!("bad grammar hash `{h}`")))?);
+                }
+                ("option", Some(name), Some(value)) => replay.options = parse_option(replay.options, name, value).map_err(err)?,
+                (time, Some(what), rest) => {
+                    let time = time.parse::<u128>().map_err(|_| err("expected a timestamp in ms".to_string()))?;
+                    let action = match (what, rest) {
+                        ("press", Some(key)) => Action::Key(KeyEventKind::Press, key.to_string()),
+                        ("release", Some(key)) => Action::Key(KeyEventKind::Release, key.to_string()),
+                        ("next", None) => Action::NextCharacter,
+                        ("flip", None) => Action::Flip,
+                        ("end", None) => {
+                            replay.finish(time);
+                            continue;
+                        }
+                        ("move", Some(rest)) => {
+                            let (player, name) = rest
+                                .split_once(char::is_whitespace)
+                                .and_then(|(p, n)| Some((p.parse::<usize>().ok().filter(|&p| p >= 1)?, n.trim())))
+                                .ok_or(err("expected `move <player> <name>`".to_string()))?;
+                            replay.moves.push(RecordedMove { time, player: player - 1, name: name.to_string() });
+                            continue;
+                        }
+                        _ => return Err(err(format!("unexpected `{line}`"))),
+                    };
+                    replay.push_action(time, action);
+                }
+                _ => return Err(err(format!("unexpected `{line}`"))),
+            }
+        }
+        replay.grammar_hash = hash.ok_or("missing `grammar` line")?;
+        Ok(replay)
+    }
+}
+
+/* Runs the recorded actions through a fresh session, the way the frontends
+ * do: key tokens go through `step_versus_outcome`, and the engines get a
+ * last tick at the end time so held-back moves fire as they did live. */
+pub fn run_replay(cfg: &VersusConfig, st: VersusState, replay: &Replay) -> (VersusState, Vec<RecordedMove>) {
+    let moves_of = |time: u128, outcomes: Vec<(usize, StepOutcome)>| -> Vec<RecordedMove> {
+        outcomes
+            .into_iter()
+            .flat_map(|(player, o)| o.moves.into_iter().map(move |m| RecordedMove { time, player, name: m.name }))
+            .collect()
+    };
+    let (st, mut fired) = replay.actions.iter().fold((st, Vec::new()), |(st, mut fired), (time, action)| {
+        let st = match action {
+            Action::Key(kind, key) => {
+                let ev = InputEvent { key: key.clone(), kind: *kind, time: *time };
+                let (st, outcomes) = step_versus_outcome(cfg, st, &ev);
+                fired.extend(moves_of(*time, outcomes));
+                st
+            }
+            Action::NextCharacter => next_characters(cfg, st),
+            Action::Flip => flip_all(cfg, st),
+        };
+        (st, fired)
+    });
+    let (st, outcomes) = tick_versus(cfg, st, replay.end_ms);
+    fired.extend(moves_of(replay.end_ms, outcomes));
+    (st, fired)
+}
+
+/* The n-th move (0-based) differs between the recording and the replay;
+ * None means that side has no n-th move. Times are not compared: a move
+ * fired by a timer is only replayed at the next action. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub index: usize,
+    pub recorded: Option<RecordedMove>,
+    pub replayed: Option<RecordedMove>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let describe = |m: &Option<RecordedMove>| match m {
+            Some(m) => format!("P{} {} at {}ms", m.player + 1, m.name, m.time),
+            None => "nothing".to_string(),
+        };
+        write!(f, "move #{}: recorded {}, replayed {}", self.index + 1, describe(&self.recorded), describe(&self.replayed))
+    }
+}
+
+pub fn divergences(recorded: &[RecordedMove], replayed: &[RecordedMove]) -> Vec<Divergence> {
+    (0..recorded.len().max(replayed.len()))
+        .map(|index| Divergence { index, recorded: recorded.get(index).cloned(), replayed: replayed.get(index).cloned() })
+        .filter(|d| match (&d.recorded, &d.replayed) {
+            (Some(a), Some(b)) => (a.player, &a.name) != (b.player, &b.name),
+            _ => true,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::load_grammar;
+    use crate::parse::{classify, parse_gmr};
+    use crate::policy::ResolutionPolicy;
+    use crate::versus::versus_from_gmr_file;
+
+    const TEST: &str = "grammar/test.gmr";
+
+    fn record(cfg: &VersusConfig, st: VersusState, keys: &[(&str, u128)]) -> Replay {
+        let mut replay = Replay::new(cfg.grammar_hash, &EngineOptions::default());
+        keys.iter().fold(st, |st, (key, time)| {
+            replay.push_action(*time, Action::Key(KeyEventKind::Press, key.to_string()));
+            let (st, outcomes) = step_versus_outcome(cfg, st, &InputEvent::press(*key, *time));
+            replay.push_moves(*time, &outcomes);
+            st
+        });
+        replay.finish(2000);
+        replay
+    }
+
+    #[test]
+    fn text_form_round_trips() {
+        let (cfg, st) = versus_from_gmr_file(TEST, &EngineOptions::default()).unwrap();
+        let mut replay = record(&cfg, st, &[("down", 0), ("right", 100), ("w", 200), ("q", 900)]);
+        replay.push_action(1000, Action::Flip);
+        replay.push_action(1100, Action::NextCharacter);
+        replay.push_action(1200, Action::Key(KeyEventKind::Release, "q".into()));
+        replay.options.resolution = ResolutionPolicy::LongestMatch;
+        replay.options.pinned.resolution = true;
+
+        let text = replay.to_string();
+        assert!(text.starts_with("ft_ality-replay 1\n"));
+        /* left to the grammar */
+        assert!(!text.contains("option timeout_ms") && text.contains("option resolution longest_match\n"));
+        assert!(text.contains("200 press w\n200 move 1 Fireball (Generic)\n"), "{text}");
+        assert_eq!(text.parse::<Replay>().unwrap(), replay);
+
+        assert!("ft_ality-replay 2\ngrammar 0\n".parse::<Replay>().unwrap_err().contains("version 2"));
+        assert!("ft_ality-replay 1\n".parse::<Replay>().unwrap_err().contains("grammar"));
+        let bad = "ft_ality-replay 1\ngrammar 0\n12 jump w\n".parse::<Replay>().unwrap_err();
+        assert!(bad.starts_with("line 3:"), "{bad}");
+    }
+
+    #[test]
+    fn replay_reproduces_the_session_and_reports_divergence() {
+        let (cfg, st) = versus_from_gmr_file(TEST, &EngineOptions::default()).unwrap();
+        let replay = record(&cfg, st.clone(), &[("down", 0), ("right", 100), ("w", 200), ("q", 900), ("w", 1000)]);
+        assert_eq!(replay.moves.len(), 3);
+
+        let (_, fired) = run_replay(&cfg, st.clone(), &replay);
+        assert_eq!(divergences(&replay.moves, &fired), vec![]);
+
+        /* the tester "remembers" a Fireball that never happened */
+        let mut claimed = replay.clone();
+        claimed.moves.insert(1, RecordedMove { time: 200, player: 0, name: "Fireball (Generic)".into() });
+        let (_, fired) = run_replay(&cfg, st, &claimed);
+        let found = divergences(&claimed.moves, &fired);
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[0].to_string(), "move #2: recorded P1 Fireball (Generic) at 200ms, replayed P1 Claw Slam (Freddy Krueger) at 900ms");
+        assert_eq!(found[2].replayed, None);
+    }
+
+    #[test]
+    fn grammar_hash_ignores_layout_but_not_content() {
+        let hash = |src: &str| grammar_hash(&classify(&parse_gmr(src).unwrap()));
+        let base = hash("w -> [FP]\n[FP], [FP] -> Double\n");
+        assert_eq!(base, hash("# bindings\nw  ->  [FP]\n\n[FP],[FP] -> Double\n"));
+        assert_ne!(base, hash("w -> [FP]\n[FP], [FP] -> Triple\n"));
+        assert_ne!(base, hash("w -> [FP]\n[FP], [FP] -> Double {max_gap=100}\n"));
+        assert_ne!(base, hash("w -> [FP]\n[FP], [FP] -> Double\nset timeout_ms = 300\n"));
+
+        let (compiled, _) = load_grammar(TEST, &EngineOptions::default()).unwrap();
+        assert_eq!(versus_from_gmr_file(TEST, &EngineOptions::default()).unwrap().0.grammar_hash, grammar_hash(&compiled));
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+}
@@ -0,0 +1,229 @@
+use crate::diag::Diagnostic;
+use crate::engine::{
+    engine_for_player, flip, flush, format_engine_info, load_grammar, next_character, step_event_outcome, tick,
+    EngineConfig, EngineOptions, EngineState, MoveHit, StepOutcome,
+};
+use crate::facing::Facing;
+use crate::input::InputEvent;
+use crate::replay::grammar_hash;
+
+/* One engine per `[player N]` section, index 0 being player 1. A grammar
+ * without player sections has a single player. Odd players start on the
+ * left facing right, even ones on the right facing left. */
+#[derive(Debug, Clone)]
+pub struct VersusConfig {
+    pub players: Vec<EngineConfig>,
+    pub warnings: Vec<Diagnostic>,
+    /* `replay::grammar_hash` of the grammar, so recordings can be matched to it */
+    pub grammar_hash: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersusState {
+    pub players: Vec<EngineState>,
+}
+
+/* Players a key token is fed to: every player it is bound for. A key
+ * nobody binds goes to a lone player (for its `on_unknown_key` policy),
+ * but is dropped in versus, so a stray key cannot reset someone else's
+ * combo. */
+pub fn route(cfg: &VersusConfig, key: &str) -> Vec<usize> {
+    let bound: Vec<usize> = cfg
+        .players
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| p.key_to_internal.contains_key(key))
+        .map(|(i, _)| i)
+        .collect();
+    if bound.is_empty() && cfg.players.len() == 1 { vec![0] } else { bound }
+}
+
+/* Steps the players `ev.key` routes to; each routed player's outcome
+ * comes back tagged with its index. */
+pub fn step_versus_outcome(cfg: &VersusConfig, st: VersusState, ev: &InputEvent)
+    -> (VersusState, Vec<(usize, StepOutcome)>)
+{
+    let targets = route(cfg, &ev.key);
+    let mut outcomes = Vec::new();
+    let players = st
+        .players
+        .into_iter()
+        .enumerate()
+        .map(|(i, pst)| {
+            if !targets.contains(&i) {
+                return pst;
+            }
+            let (pst, outcome) = step_event_outcome(&cfg.players[i], pst, ev);
+            outcomes.push((i, outcome));
+            pst
+        })
+        .collect();
+    (VersusState { players }, outcomes)
+}
+
+/* Moves only, tagged with the player index. */
+pub fn step_versus(cfg: &VersusConfig, st: VersusState, ev: &InputEvent)
+    -> (VersusState, Vec<(usize, MoveHit)>)
+{
+    let (st, outcomes) = step_versus_outcome(cfg, st, ev);
+    let fired = outcomes.into_iter().flat_map(|(i, o)| o.moves.into_iter().map(move |m| (i, m))).collect();
+    (st, fired)
+}
+
+/* `engine::tick` for every player; only players with something to report
+ * come back. */
+pub fn tick_versus(cfg: &VersusConfig, st: VersusState, now_ms: u128) -> (VersusState, Vec<(usize, StepOutcome)>) {
+    let mut outcomes = Vec::new();
+    let players = cfg
+        .players
+        .iter()
+        .zip(st.players)
+        .enumerate()
+        .map(|(i, (c, pst))| {
+            let (pst, outcome) = tick(c, pst, now_ms);
+            if outcome != StepOutcome::default() {
+                outcomes.push((i, outcome));
+            }
+            pst
+        })
+        .collect();
+    (VersusState { players }, outcomes)
+}
+
+/* `engine::flush` for every player, at the end of the input. */
+pub fn flush_versus(cfg: &VersusConfig, st: VersusState) -> (VersusState, Vec<(usize, StepOutcome)>) {
+    let mut outcomes = Vec::new();
+    let players = cfg
+        .players
+        .iter()
+        .zip(st.players)
+        .enumerate()
+        .map(|(i, (c, pst))| {
+            let (pst, outcome) = flush(c, pst);
+            if outcome != StepOutcome::default() {
+                outcomes.push((i, outcome));
+            }
+            pst
+        })
+        .collect();
+    (VersusState { players }, outcomes)
+}
+
+/* Every player switches to their next character. */
+pub fn next_characters(cfg: &VersusConfig, st: VersusState) -> VersusState {
+    let players = cfg.players.iter().zip(st.players).map(|(c, s)| next_character(c, s)).collect();
+    VersusState { players }
+}
+
+/* Every player swaps sides. */
+pub fn flip_all(cfg: &VersusConfig, st: VersusState) -> VersusState {
+    let players = cfg.players.iter().zip(st.players).map(|(c, s)| flip(c, s)).collect();
+    VersusState { players }
+}
+
+pub fn format_versus_info(cfg: &VersusConfig, st: &VersusState) -> String {
+    if cfg.players.len() == 1 {
+        return format_engine_info(&cfg.players[0], &st.players[0]);
+    }
+    cfg.players
+        .iter()
+        .zip(&st.players)
+        .enumerate()
+        .map(|(i, (c, s))| format!("=== Player {} ===\n{}", i + 1, format_engine_info(c, s)))
+        .collect()
+}
+
+pub fn print_versus(cfg: &VersusConfig, st: &VersusState) {
+    print!("{}", format_versus_info(cfg, st));
+}
+
+pub fn versus_from_gmr_file(path: &str, opts: &EngineOptions) -> Result<(VersusConfig, VersusState), String> {
+    let (compiled, warnings) = load_grammar(path, opts)?;
+    let (players, states) = (1..=compiled.players())
+        .map(|p| {
+            let (cfg, st) = engine_for_player(&compiled, p, opts);
+            let facing = if p % 2 == 1 { Facing::Right } else { Facing::Left };
+            (cfg, EngineState { facing, ..st })
+        })
+        .unzip();
+    let grammar_hash = grammar_hash(&compiled);
+    Ok((VersusConfig { players, warnings, grammar_hash }, VersusState { players: states }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::engine::DEFAULT_STEP_TIMEOUT;
+
+    const VERSUS: &str = "grammar/versus.gmr";
+
+    fn press(cfg: &VersusConfig, st: VersusState, key: &str, time: u128) -> (VersusState, Vec<(usize, String)>) {
+        let (st, fired) = step_versus(cfg, st, &InputEvent::press(key, time));
+        (st, fired.into_iter().map(|(i, m)| (i, m.name)).collect())
+    }
+
+    #[test]
+    fn keys_go_to_their_player() {
+        let (cfg, st) = versus_from_gmr_file(VERSUS, &EngineOptions::default()).unwrap();
+        assert_eq!(cfg.players.len(), 2);
+        assert_eq!(route(&cfg, "a"), vec![0]);
+        assert_eq!(route(&cfg, "left"), vec![1]);
+        assert!(route(&cfg, "nobody").is_empty());
+
+        /* interleaved inputs do not disturb each other */
+        let (st, _) = press(&cfg, st, "a", 0);
+        let (st, _) = press(&cfg, st, "right", 10);
+        let (st, p1) = press(&cfg, st, "d", 100);
+        let (_, p2) = press(&cfg, st, "left", 110);
+        assert_eq!(p1, vec![(0, "Dash".to_string())]);
+        assert_eq!(p2, vec![(1, "Dash".to_string())]);
+    }
+
+    #[test]
+    fn unbound_keys_do_not_reset_either_player() {
+        let (cfg, st) = versus_from_gmr_file(VERSUS, &EngineOptions::default()).unwrap();
+        let (st, _) = press(&cfg, st, "a", 0);
+        let (st, _) = press(&cfg, st, "right", 10);
+        let (st, _) = press(&cfg, st, "x", 50);
+        let (st, p1) = press(&cfg, st, "d", 100);
+        let (_, p2) = press(&cfg, st, "left", 110);
+        assert_eq!(p1, vec![(0, "Dash".to_string())]);
+        assert_eq!(p2, vec![(1, "Dash".to_string())]);
+
+        /* alone, a player still sees them */
+        let (cfg, _) = versus_from_gmr_file("grammar/test.gmr", &EngineOptions::default()).unwrap();
+        assert_eq!(route(&cfg, "x"), vec![0]);
+    }
+
+    #[test]
+    fn players_face_each_other() {
+        let (cfg, st) = versus_from_gmr_file(VERSUS, &EngineOptions::default()).unwrap();
+        let facing: Vec<Facing> = st.players.iter().map(|p| p.facing).collect();
+        assert_eq!(facing, vec![Facing::Right, Facing::Left]);
+        let st = flip_all(&cfg, st);
+        assert_eq!(st.players[1].facing, Facing::Right);
+    }
+
+    #[test]
+    fn each_player_has_its_own_timeout() {
+        let (cfg, st) = versus_from_gmr_file(VERSUS, &EngineOptions::default()).unwrap();
+        assert_eq!(cfg.players[0].step_timeout, DEFAULT_STEP_TIMEOUT);
+        assert_eq!(cfg.players[1].step_timeout, Duration::from_millis(800));
+
+        let (st, _) = press(&cfg, st, "a", 0);
+        let (st, _) = press(&cfg, st, "right", 0);
+        let (st, p1) = press(&cfg, st, "d", 700);
+        let (_, p2) = press(&cfg, st, "left", 700);
+        assert!(p1.is_empty());
+        assert_eq!(p2, vec![(1, "Dash".to_string())]);
+    }
+
+    #[test]
+    fn single_player_grammar_has_one_player() {
+        let (cfg, st) = versus_from_gmr_file("grammar/test.gmr", &EngineOptions::default()).unwrap();
+        assert_eq!((cfg.players.len(), st.players.len()), (1, 1));
+        assert!(!format_versus_info(&cfg, &st).contains("Player"));
+    }
+}